@@ -0,0 +1,281 @@
+//! Admission policy deciding which DIDs the AS issues credentials to.
+//!
+//! A policy is a list of rules that must all accept the request. The built-in rules cover a
+//! static allowlist, a DID pattern, an invite token and a cap on the number of participants,
+//! other rules can be added with [`AdmissionPolicy::with_rule`].
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::revocation::RevocationRegistry;
+
+/// What a rule knows about a credential request.
+pub struct AdmissionRequest<'a> {
+    pub did: &'a str,
+    /// Invite token sent by the client, `None` when renewing a credential.
+    pub invite_token: Option<&'a str>,
+    /// True when the DID was admitted before.
+    pub known: bool,
+    /// Number of admitted DIDs whose credentials were not revoked.
+    pub participants: usize,
+}
+
+pub trait AdmissionRule: Send + Sync {
+    /// Name of the rule in the logs.
+    fn name(&self) -> &'static str;
+
+    /// Returns the reason of the denial when `request` is refused.
+    fn check(&self, request: &AdmissionRequest) -> Result<(), String>;
+}
+
+/// Admits only the DIDs listed in a file, one per line. Lines starting with `#` are ignored.
+pub struct Allowlist {
+    dids: BTreeSet<String>,
+}
+
+impl Allowlist {
+    pub fn load(path: &Path) -> io::Result<Allowlist> {
+        let content = fs::read_to_string(path)?;
+        let dids = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        Ok(Allowlist { dids })
+    }
+}
+
+impl AdmissionRule for Allowlist {
+    fn name(&self) -> &'static str {
+        "allowlist"
+    }
+
+    fn check(&self, request: &AdmissionRequest) -> Result<(), String> {
+        if self.dids.contains(request.did) {
+            Ok(())
+        } else {
+            Err(String::from("the DID is not on the allowlist"))
+        }
+    }
+}
+
+/// Admits the DIDs matching a pattern where `*` stands for any sequence of characters,
+/// e.g. `did:iota:dev:*`.
+pub struct DidPattern {
+    pattern: String,
+}
+
+impl DidPattern {
+    pub fn new(pattern: String) -> DidPattern {
+        DidPattern { pattern }
+    }
+}
+
+impl AdmissionRule for DidPattern {
+    fn name(&self) -> &'static str {
+        "did_pattern"
+    }
+
+    fn check(&self, request: &AdmissionRequest) -> Result<(), String> {
+        if wildcard_match(&self.pattern, request.did) {
+            Ok(())
+        } else {
+            Err(format!("the DID does not match {}", self.pattern))
+        }
+    }
+}
+
+/// Requires a shared invite token for the first credential of a DID. Renewals do not carry it.
+pub struct InviteToken {
+    token: String,
+}
+
+impl InviteToken {
+    pub fn new(token: String) -> InviteToken {
+        InviteToken { token }
+    }
+}
+
+impl AdmissionRule for InviteToken {
+    fn name(&self) -> &'static str {
+        "invite_token"
+    }
+
+    fn check(&self, request: &AdmissionRequest) -> Result<(), String> {
+        match request.invite_token {
+            _ if request.known => Ok(()),
+            None | Some("") => Err(String::from("an invite token is required")),
+            Some(token) if constant_time_eq(token.as_bytes(), self.token.as_bytes()) => Ok(()),
+            _ => Err(String::from("invalid invite token")),
+        }
+    }
+}
+
+/// Caps the number of participants. DIDs admitted before always get their credentials renewed.
+pub struct MaxParticipants {
+    max: usize,
+}
+
+impl MaxParticipants {
+    pub fn new(max: usize) -> MaxParticipants {
+        MaxParticipants { max }
+    }
+}
+
+impl AdmissionRule for MaxParticipants {
+    fn name(&self) -> &'static str {
+        "max_participants"
+    }
+
+    fn check(&self, request: &AdmissionRequest) -> Result<(), String> {
+        if request.known || request.participants < self.max {
+            Ok(())
+        } else {
+            Err(format!("the federation already has {} participants", self.max))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Denial {
+    pub rule: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.reason, self.rule)
+    }
+}
+
+#[derive(Debug)]
+pub enum AdmissionError {
+    Denied(Denial),
+    /// The registry could not record the admission.
+    Io(io::Error),
+}
+
+#[derive(Default)]
+pub struct AdmissionPolicy {
+    rules: Vec<Box<dyn AdmissionRule>>,
+    /// Serializes admissions, so concurrent requests cannot exceed the participant cap.
+    lock: Mutex<()>,
+}
+
+impl AdmissionPolicy {
+    /// Policy made of the rules enabled in `config`. Without any, every DID is admitted.
+    pub fn from_config(config: &Config) -> io::Result<AdmissionPolicy> {
+        let mut policy = AdmissionPolicy::default();
+        if let Some(path) = &config.allowlist_file {
+            let allowlist = Allowlist::load(path)
+                .map_err(|err| io::Error::new(err.kind(), format!("cannot read allowlist {}: {}", path.display(), err)))?;
+            policy = policy.with_rule(allowlist);
+        }
+        if let Some(pattern) = &config.did_pattern {
+            policy = policy.with_rule(DidPattern::new(pattern.clone()));
+        }
+        if let Some(token) = &config.invite_token {
+            policy = policy.with_rule(InviteToken::new(token.clone()));
+        }
+        if let Some(max) = config.max_participants {
+            policy = policy.with_rule(MaxParticipants::new(max));
+        }
+        Ok(policy)
+    }
+
+    pub fn with_rule(mut self, rule: impl AdmissionRule + 'static) -> AdmissionPolicy {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Names of the active rules.
+    pub fn rules(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|rule| rule.name()).collect()
+    }
+
+    /// Checks `did` against every rule and reserves the bitmap index of its new credential.
+    pub fn admit(&self, registry: &RevocationRegistry, did: &str, invite_token: Option<&str>) -> Result<u32, AdmissionError> {
+        let _guard = self.lock.lock().unwrap();
+        let request = AdmissionRequest {
+            did,
+            invite_token,
+            known: registry.has_issued(did),
+            participants: registry.active_holders(),
+        };
+        for rule in &self.rules {
+            rule.check(&request)
+                .map_err(|reason| AdmissionError::Denied(Denial { rule: rule.name(), reason }))?;
+        }
+        registry.allocate(did).map_err(AdmissionError::Io)
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` stands for any sequence of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    if text.len() < first.len() + last.len() || !text.starts_with(first) || !text.ends_with(last) {
+        return false;
+    }
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(start) => rest = &rest[start + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Compares without returning early, so the time taken does not leak the token.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_without_wildcard_matches_exactly() {
+        assert!(wildcard_match("did:iota:dev:abc", "did:iota:dev:abc"));
+        assert!(!wildcard_match("did:iota:dev:abc", "did:iota:dev:abcd"));
+        assert!(!wildcard_match("did:iota:dev:abc", "did:iota:dev:ab"));
+    }
+
+    #[test]
+    fn wildcard_matches_any_sequence() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "did:iota:abc"));
+        assert!(wildcard_match("did:iota:dev:*", "did:iota:dev:abc"));
+        assert!(wildcard_match("did:iota:dev:*", "did:iota:dev:"));
+        assert!(!wildcard_match("did:iota:dev:*", "did:iota:main:abc"));
+        assert!(wildcard_match("*:abc", "did:iota:dev:abc"));
+        assert!(!wildcard_match("*:abc", "did:iota:dev:abd"));
+    }
+
+    #[test]
+    fn several_wildcards_match_in_order() {
+        assert!(wildcard_match("did:*:dev:*", "did:iota:dev:abc"));
+        assert!(wildcard_match("*iota*dev*", "did:iota:dev:abc"));
+        assert!(!wildcard_match("*dev*iota*", "did:iota:dev:abc"));
+        assert!(wildcard_match("a**b", "ab"));
+    }
+
+    #[test]
+    fn prefix_and_suffix_do_not_overlap() {
+        //"aba" starts with "ab" and ends with "ba", but has no room for both
+        assert!(!wildcard_match("ab*ba", "aba"));
+        assert!(wildcard_match("ab*ba", "abba"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+}
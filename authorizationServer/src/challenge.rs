@@ -0,0 +1,88 @@
+//! Registry of the challenges handed out for VP verification.
+//!
+//! Each challenge is bound to the connection and DID that asked for it and can be redeemed once.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use identity_iota::core::{Duration, Timestamp};
+use identity_iota::iota_core::IotaDID;
+
+use crate::create_nonce;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChallengeError {
+    /// Never issued, already redeemed or garbage-collected.
+    Unknown,
+    Expired(Timestamp),
+    /// Redeemed from a different connection or for a different DID than it was issued to.
+    Mismatch,
+}
+
+impl fmt::Display for ChallengeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChallengeError::Unknown => write!(f, "unknown or already used challenge"),
+            ChallengeError::Expired(expires) => write!(f, "challenge expired at {}", expires),
+            ChallengeError::Mismatch => write!(f, "challenge was issued to another client"),
+        }
+    }
+}
+
+struct IssuedChallenge {
+    peer: SocketAddr,
+    did: IotaDID,
+    expires: Timestamp,
+}
+
+pub struct ChallengeStore {
+    ttl: Duration,
+    issued: Mutex<HashMap<String, IssuedChallenge>>,
+}
+
+impl ChallengeStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, issued: Mutex::new(HashMap::new()) }
+    }
+
+    /// Creates a fresh challenge for `did` on the connection from `peer`.
+    pub fn issue(&self, peer: SocketAddr, did: &IotaDID) -> (String, Timestamp) {
+        //Config::load bounds the TTL, so the expiry always fits in a timestamp
+        let expires: Timestamp = Timestamp::now_utc().checked_add(self.ttl).expect("challenge TTL out of range");
+        let mut issued = self.issued.lock().unwrap();
+        loop {
+            let challenge = create_nonce();
+            if issued.contains_key(&challenge) {
+                continue;
+            }
+            issued.insert(challenge.clone(), IssuedChallenge { peer, did: did.clone(), expires });
+            return (challenge, expires);
+        }
+    }
+
+    /// Consumes `challenge`, returning its expiry if it was issued to `peer` and `did` and is still valid.
+    pub fn redeem(&self, challenge: &str, peer: SocketAddr, did: &IotaDID) -> Result<Timestamp, ChallengeError> {
+        let entry = match self.issued.lock().unwrap().remove(challenge) {
+            Some(entry) => entry,
+            None => return Err(ChallengeError::Unknown),
+        };
+
+        if entry.peer != peer || &entry.did != did {
+            return Err(ChallengeError::Mismatch);
+        }
+        if Timestamp::now_utc() > entry.expires {
+            return Err(ChallengeError::Expired(entry.expires));
+        }
+        Ok(entry.expires)
+    }
+
+    /// Drops every expired challenge and returns how many were removed.
+    pub fn purge_expired(&self) -> usize {
+        let now = Timestamp::now_utc();
+        let mut issued = self.issued.lock().unwrap();
+        let before = issued.len();
+        issued.retain(|_, entry| entry.expires >= now);
+        before - issued.len()
+    }
+}
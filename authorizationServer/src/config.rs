@@ -0,0 +1,298 @@
+//! Deployment settings of the Authorization Service.
+//!
+//! Values come from the built-in defaults, then an optional TOML file, then environment
+//! variables and command line flags (the last two are handled together by clap).
+
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::credential::Role;
+
+#[derive(Debug, Parser)]
+#[command(name = "authorizationServer", about = "Issues and verifies the credentials of the federation")]
+pub struct Cli {
+    /// TOML configuration file.
+    #[arg(long, env = "FETA_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// IOTA network name.
+    #[arg(long, env = "FETA_NETWORK")]
+    pub network: Option<String>,
+
+    /// URL of the Tangle node.
+    #[arg(long, env = "FETA_NODE_URL")]
+    pub node_url: Option<String>,
+
+    /// Address the AS listens on.
+    #[arg(long, env = "FETA_LISTEN_ADDR")]
+    pub listen_addr: Option<String>,
+
+    /// Directory holding the Stronghold and the DID file.
+    #[arg(long, env = "FETA_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Maximum number of clients served at the same time.
+    #[arg(long, env = "FETA_MAX_SESSIONS")]
+    pub max_sessions: Option<usize>,
+
+    /// Seconds a client may stay silent before its connection is closed.
+    #[arg(long, env = "FETA_IDLE_TIMEOUT_SECS")]
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Lifetime of a VP challenge, in minutes.
+    #[arg(long, env = "FETA_CHALLENGE_TTL_MINUTES")]
+    pub challenge_ttl_minutes: Option<i64>,
+
+    /// Validity of the issued VCs, in hours.
+    #[arg(long, env = "FETA_CREDENTIAL_LIFETIME_HOURS")]
+    pub credential_lifetime_hours: Option<i64>,
+
+    /// Federated learning session the issued VCs are restricted to.
+    #[arg(long, env = "FETA_SESSION_ID")]
+    pub session_id: Option<String>,
+
+    /// TOML file enrolling participants with a role and a dataset.
+    #[arg(long, env = "FETA_PARTICIPANTS_FILE")]
+    pub participants_file: Option<PathBuf>,
+
+    /// Role granted to holders missing from the participants file.
+    #[arg(long, env = "FETA_DEFAULT_ROLE", value_enum)]
+    pub default_role: Option<Role>,
+
+    /// File listing the DIDs admitted to the federation, one per line.
+    #[arg(long, env = "FETA_ALLOWLIST_FILE")]
+    pub allowlist_file: Option<PathBuf>,
+
+    /// Pattern the DIDs of the participants must match, `*` matching anything.
+    #[arg(long, env = "FETA_DID_PATTERN")]
+    pub did_pattern: Option<String>,
+
+    /// Token clients must present to obtain their first VC.
+    #[arg(long, env = "FETA_INVITE_TOKEN", hide_env_values = true)]
+    pub invite_token: Option<String>,
+
+    /// Maximum number of participants holding a VC.
+    #[arg(long, env = "FETA_MAX_PARTICIPANTS")]
+    pub max_participants: Option<usize>,
+
+    /// File holding the Stronghold password.
+    #[arg(long, env = "FETA_PASSWORD_FILE")]
+    pub password_file: Option<PathBuf>,
+
+    /// Publish a new issuer identity even if one already exists.
+    #[arg(long)]
+    pub rotate_identity: bool,
+
+    /// Ask the running AS to revoke the credentials of this DID, then exit.
+    #[arg(long, value_name = "DID")]
+    pub revoke: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: String,
+    pub node_url: String,
+    pub listen_addr: String,
+    pub data_dir: PathBuf,
+    pub password_file: Option<PathBuf>,
+    pub max_sessions: usize,
+    /// Seconds a client may stay silent before its connection is closed.
+    pub idle_timeout_secs: u64,
+    /// Validity of the issued VCs, in hours.
+    pub credential_lifetime_hours: i64,
+    /// Written in every issued VC, clients then take it as their session.
+    pub session_id: Option<String>,
+    /// Lifetime of a VP challenge, in minutes.
+    pub challenge_ttl_minutes: i64,
+    /// Role and dataset of each participant DID.
+    pub participants_file: Option<PathBuf>,
+    /// Role of the holders missing from the participants file.
+    pub default_role: Role,
+    /// DIDs admitted to the federation, one per line.
+    pub allowlist_file: Option<PathBuf>,
+    /// Pattern the DIDs of the participants must match.
+    pub did_pattern: Option<String>,
+    /// Token clients must present to obtain their first VC.
+    pub invite_token: Option<String>,
+    /// Maximum number of participants holding a VC.
+    pub max_participants: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            network: String::from("dev"),
+            node_url: String::from("http://192.168.10.203:14265"),
+            listen_addr: String::from("0.0.0.0:3333"),
+            data_dir: PathBuf::from("/mnt"),
+            password_file: None,
+            max_sessions: 256,
+            idle_timeout_secs: 60,
+            credential_lifetime_hours: 24,
+            session_id: None,
+            challenge_ttl_minutes: 10,
+            participants_file: None,
+            default_role: Role::Trainer,
+            allowlist_file: None,
+            did_pattern: None,
+            invite_token: None,
+            max_participants: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(cli: &Cli) -> io::Result<Config> {
+        let mut config: Config = match &cli.config {
+            Some(path) => {
+                let content = fs::read_to_string(path)?;
+                toml::from_str(&content).map_err(|err| invalid_input(err.to_string()))?
+            },
+            None => Config::default(),
+        };
+
+        if let Some(network) = &cli.network {
+            config.network = network.clone();
+        }
+        if let Some(node_url) = &cli.node_url {
+            config.node_url = node_url.clone();
+        }
+        if let Some(listen_addr) = &cli.listen_addr {
+            config.listen_addr = listen_addr.clone();
+        }
+        if let Some(data_dir) = &cli.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+        if let Some(password_file) = &cli.password_file {
+            config.password_file = Some(password_file.clone());
+        }
+        if let Some(max_sessions) = cli.max_sessions {
+            config.max_sessions = max_sessions;
+        }
+        if let Some(idle_timeout_secs) = cli.idle_timeout_secs {
+            config.idle_timeout_secs = idle_timeout_secs;
+        }
+        if let Some(challenge_ttl_minutes) = cli.challenge_ttl_minutes {
+            config.challenge_ttl_minutes = challenge_ttl_minutes;
+        }
+        if let Some(credential_lifetime_hours) = cli.credential_lifetime_hours {
+            config.credential_lifetime_hours = credential_lifetime_hours;
+        }
+        if let Some(session_id) = &cli.session_id {
+            config.session_id = Some(session_id.clone());
+        }
+        if let Some(participants_file) = &cli.participants_file {
+            config.participants_file = Some(participants_file.clone());
+        }
+        if let Some(default_role) = cli.default_role {
+            config.default_role = default_role;
+        }
+        if let Some(allowlist_file) = &cli.allowlist_file {
+            config.allowlist_file = Some(allowlist_file.clone());
+        }
+        if let Some(did_pattern) = &cli.did_pattern {
+            config.did_pattern = Some(did_pattern.clone());
+        }
+        if let Some(invite_token) = &cli.invite_token {
+            config.invite_token = Some(invite_token.clone());
+        }
+        if let Some(max_participants) = cli.max_participants {
+            config.max_participants = Some(max_participants);
+        }
+
+        if config.max_sessions == 0 {
+            return Err(invalid_input(String::from("the maximum number of sessions must be at least 1")));
+        }
+        if config.idle_timeout_secs == 0 {
+            return Err(invalid_input(String::from("the idle timeout must be at least 1 second")));
+        }
+        if !(1..=24 * 60).contains(&config.challenge_ttl_minutes) {
+            return Err(invalid_input(String::from("the challenge TTL must be between 1 minute and 1 day")));
+        }
+        //Bounded so the expiration date always fits in a timestamp
+        if !(1..=24 * 366 * 10).contains(&config.credential_lifetime_hours) {
+            return Err(invalid_input(String::from("the credential lifetime must be between 1 hour and 10 years")));
+        }
+        if let Some(session_id) = &config.session_id {
+            check_session_id(session_id)?;
+        }
+        if config.did_pattern.as_deref() == Some("") {
+            return Err(invalid_input(String::from("the DID pattern must not be empty")));
+        }
+        if config.invite_token.as_deref() == Some("") {
+            return Err(invalid_input(String::from("the invite token must not be empty")));
+        }
+        if config.max_participants == Some(0) {
+            return Err(invalid_input(String::from("the maximum number of participants must be at least 1")));
+        }
+        Ok(config)
+    }
+
+    /// Password of the Stronghold, taken from `FETA_STRONGHOLD_PASSWORD`, the password file or
+    /// an interactive prompt, in this order. Empty passwords are refused for new Strongholds, and
+    /// only accepted with a warning for those created before they were.
+    pub fn stronghold_password(&self) -> io::Result<String> {
+        let password = if let Ok(password) = env::var("FETA_STRONGHOLD_PASSWORD") {
+            password
+        } else if let Some(path) = &self.password_file {
+            let content = fs::read_to_string(path)
+                .map_err(|err| io::Error::new(err.kind(), format!("cannot read password file {}: {}", path.display(), err)))?;
+            content.trim_end_matches(['\n', '\r']).to_string()
+        } else if io::stdin().is_terminal() {
+            let password = rpassword::prompt_password("Insert Stronghold password: ")?;
+            if !self.path("strong.hodl").exists() {
+                let confirmation = rpassword::prompt_password("No Stronghold found, confirm the password for the new one: ")?;
+                if confirmation != password {
+                    return Err(invalid_input(String::from("the passwords do not match")));
+                }
+            }
+            password
+        } else {
+            return Err(invalid_input(String::from(
+                "no Stronghold password: set FETA_STRONGHOLD_PASSWORD, --password-file or run interactively",
+            )));
+        };
+
+        if password.is_empty() {
+            let stronghold = self.path("strong.hodl");
+            if !stronghold.exists() {
+                return Err(invalid_input(String::from("the Stronghold password must not be empty")));
+            }
+            eprintln!("WARNING: {} is protected by an empty password, whoever can read it holds the issuer key. Keep it readable by the AS only.", stronghold.display());
+        }
+        Ok(password)
+    }
+
+    pub fn idle_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idle_timeout_secs)
+    }
+
+    /// Location of `file` inside the data directory.
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.data_dir.join(file)
+    }
+}
+
+/// Tangle indexes hold at most 64 bytes, the session ID shares them with the
+/// `IOTAFederatedLearning#` prefix, a separator and the round number.
+pub const MAX_SESSION_ID_LEN: usize = 31;
+
+/// Session IDs are part of the Tangle index, so they are kept short and free of separators.
+pub fn check_session_id(session_id: &str) -> io::Result<()> {
+    if session_id.is_empty() || session_id.len() > MAX_SESSION_ID_LEN {
+        return Err(invalid_input(format!("the session ID must be 1 to {} characters long", MAX_SESSION_ID_LEN)));
+    }
+    if !session_id.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || byte == b'.') {
+        return Err(invalid_input(format!("the session ID {:?} may only contain letters, digits, '-', '_' and '.'", session_id)));
+    }
+    Ok(())
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,144 @@
+//! Claims of the participant credentials, and the enrolment deciding the claims of each holder.
+//!
+//! The same schema is defined in the `credential` module of the client.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use clap::ValueEnum;
+use identity_iota::core::{Duration, FromJson, OneOrMany, Timestamp, ToJson};
+use identity_iota::credential::Credential;
+use serde::{Deserialize, Serialize};
+
+pub const CREDENTIAL_TYPE: &str = "FetaParticipantCredential";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Trains on local data and publishes model updates.
+    Trainer,
+    /// Only aggregates the updates of the trainers.
+    Aggregator,
+    /// Only follows the federation.
+    Observer,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Trainer => write!(f, "trainer"),
+            Role::Aggregator => write!(f, "aggregator"),
+            Role::Observer => write!(f, "observer"),
+        }
+    }
+}
+
+/// Local dataset a trainer is enrolled with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Dataset {
+    pub id: String,
+    /// Size of the dataset, an upper bound for the sample count declared in updates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Credential subject of a participant, besides its DID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParticipantClaims {
+    pub role: Role,
+    /// Federation session (task) the credential is valid for, any session when `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<Dataset>,
+    pub valid_from: Timestamp,
+    pub valid_until: Timestamp,
+}
+
+impl ParticipantClaims {
+    /// Claims of `credential`, `None` when it is not a well-formed participant credential.
+    pub fn from_credential(credential: &Credential) -> Option<ParticipantClaims> {
+        if !credential.types.iter().any(|kind| kind == CREDENTIAL_TYPE) {
+            return None;
+        }
+        match &credential.credential_subject {
+            OneOrMany::One(subject) => {
+                let properties = subject.properties.to_json_value().ok()?;
+                ParticipantClaims::from_json_value(properties).ok()
+            },
+            OneOrMany::Many(_) => None,
+        }
+    }
+
+    /// Checks that the claims are valid now, and for `session` if the AS serves a single one.
+    /// Returns the reason otherwise.
+    pub fn check_valid_for(&self, session: Option<&str>) -> Result<(), String> {
+        match (session, &self.session) {
+            (Some(session), Some(authorized)) if session != authorized => {
+                return Err(format!("the credential was issued for session {}, not {}", authorized, session));
+            },
+            (Some(session), None) => return Err(format!("the credential is not restricted to session {}", session)),
+            _ => (),
+        }
+        let now = Timestamp::now_utc();
+        if now < self.valid_from || now > self.valid_until {
+            return Err(format!("the credential is valid from {} to {}", self.valid_from, self.valid_until));
+        }
+        Ok(())
+    }
+}
+
+/// Role and dataset of one holder in the participants file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Enrolment {
+    pub role: Option<Role>,
+    pub dataset: Option<Dataset>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ParticipantsFile {
+    participants: BTreeMap<String, Enrolment>,
+}
+
+/// Enrolment of the participants, read once at startup. Holders not listed get the default
+/// role and no dataset.
+pub struct Participants {
+    default_role: Role,
+    enrolled: BTreeMap<String, Enrolment>,
+}
+
+impl Participants {
+    pub fn load(path: Option<&Path>, default_role: Role) -> io::Result<Participants> {
+        let enrolled = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)?;
+                let file: ParticipantsFile = toml::from_str(&content)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?;
+                file.participants
+            },
+            None => BTreeMap::new(),
+        };
+        Ok(Participants { default_role, enrolled })
+    }
+
+    /// Claims of a new credential for `holder`, valid from now for `lifetime`.
+    pub fn claims_for(&self, holder: &str, session: Option<&str>, lifetime: Duration) -> ParticipantClaims {
+        let enrolment = self.enrolled.get(holder).cloned().unwrap_or_default();
+        let valid_from = Timestamp::now_utc();
+        ParticipantClaims {
+            role: enrolment.role.unwrap_or(self.default_role),
+            session: session.map(String::from),
+            dataset: enrolment.dataset,
+            valid_from,
+            valid_until: valid_from.checked_add(lifetime).unwrap(),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Error type of the identity and credential functions of the Authorization Service.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("account error: {0}")]
+    Account(#[from] identity_iota::account::Error),
+
+    #[error("Stronghold error: {0}")]
+    Storage(#[from] identity_iota::account_storage::Error),
+
+    #[error("identity client error: {0}")]
+    IdentityClient(#[from] identity_iota::client::Error),
+
+    #[error("IOTA DID error: {0}")]
+    IotaCore(#[from] identity_iota::iota_core::Error),
+
+    #[error("DID document error: {0}")]
+    Did(#[from] identity_iota::did::Error),
+
+    #[error("credential error: {0}")]
+    Credential(#[from] identity_iota::credential::Error),
+
+    #[error("serialization error: {0}")]
+    Core(#[from] identity_iota::core::Error),
+
+    /// The issuer produced a credential that does not pass its own validation.
+    #[error("invalid credential: {0}")]
+    InvalidCredential(String),
+
+    /// A presentation or proof sent by a client was refused.
+    #[error("{0}")]
+    Rejected(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
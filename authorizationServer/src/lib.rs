@@ -1,45 +1,65 @@
+pub mod admission;
+pub mod challenge;
+pub mod config;
+pub mod credential;
+pub mod error;
+pub mod protocol;
+pub mod revocation;
+
 use std::fs::File;
-use identity_iota::account::{Account, AccountBuilder, AutoSave, Error, IdentitySetup, MethodContent, Result};
-use identity_iota::client::{Client, ClientBuilder, CredentialValidationOptions, CredentialValidator, FailFast, PresentationValidationOptions, Resolver, ResolverBuilder, SubjectHolderRelationship};
-use identity_iota::core::{Duration, FromJson, json, OneOrMany, Timestamp, ToJson, Url};
-use identity_iota::credential::{Credential, CredentialBuilder, Presentation, Subject};
+use identity_iota::account::{Account, AccountBuilder, AutoSave, IdentitySetup, MethodContent};
+use identity_iota::client::{Client, ClientBuilder, CredentialValidationOptions, CredentialValidator, FailFast, PresentationValidationOptions, Resolver, ResolverBuilder, StatusCheck, SubjectHolderRelationship};
+use identity_iota::core::{FromJson, json, OneOrMany, Timestamp, ToJson, Url};
+use identity_iota::credential::{Credential, CredentialBuilder, Presentation, RevocationBitmapStatus, Status, Subject};
 use identity_iota::iota_core::{IotaDID, Network};
 use identity_iota::account_storage::Stronghold;
-use identity_iota::crypto::ProofOptions;
-use identity_iota::did::DID;
+use identity_iota::crypto::{GetSignature, GetSignatureMut, Proof, ProofOptions, SetSignature};
+use identity_iota::did::{RevocationBitmap, DID};
 use identity_iota::did::verifiable::VerifierOptions;
 use std::path::PathBuf;
 use std::sync::Arc;
 use rand_chacha::rand_core::{RngCore, SeedableRng};
 use std::io::{BufRead, BufReader, Write};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::credential::{ParticipantClaims, CREDENTIAL_TYPE};
+use crate::error::{Error, Result};
 
-pub fn write_did(did: &IotaDID) -> std::io::Result<()> {
-    let mut output = File::create("/mnt/did.txt")?;
+pub fn write_did(config: &Config, did: &IotaDID) -> std::io::Result<()> {
+    let mut output = File::create(config.path("did.txt"))?;
     write!(output, "{}", did)
 }
 
-pub fn read_did() -> std::io::Result<String> {
-    let file = File::open("/mnt/did.txt").unwrap();
-    let reader = BufReader::new(file);
-    reader.lines().enumerate().next().unwrap().1
+/// True when a previous run left both the Stronghold and the DID it holds.
+pub fn identity_exists(config: &Config) -> bool {
+    config.path("strong.hodl").exists() && config.path("did.txt").exists()
+}
+
+pub fn read_did(config: &Config) -> std::io::Result<String> {
+    let reader = BufReader::new(File::open(config.path("did.txt"))?);
+    match reader.lines().next() {
+        Some(line) => line,
+        None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "did.txt is empty")),
+    }
 }
 
-pub async fn create_client(network_name: String, url: String) -> Result<Client> {
-    let network = Network::try_from_name(network_name)?;
+pub async fn create_client(config: &Config) -> Result<Client> {
+    let network = Network::try_from_name(config.network.clone())?;
 
     let client: Client = ClientBuilder::new()
         .network(network.clone())
-        .primary_node(url.as_str(), None, None)?
+        .primary_node(config.node_url.as_str(), None, None)?
         .build()
         .await?;
     Ok(client)
 }
 
-pub async fn create_builder(password: String, network_name: String, url: String) -> Result<AccountBuilder> {
-    let stronghold_path: PathBuf = "/mnt/strong.hodl".into();
+pub async fn create_builder(password: String, config: &Config) -> Result<AccountBuilder> {
+    let stronghold_path: PathBuf = config.path("strong.hodl");
     let stronghold: Stronghold = Stronghold::new(&stronghold_path, password, None).await?;
 
-    let network = Network::try_from_name(network_name)?;
+    let network = Network::try_from_name(config.network.clone())?;
 
     let builder: AccountBuilder = Account::builder()
         .autosave(AutoSave::Every)
@@ -48,7 +68,7 @@ pub async fn create_builder(password: String, network_name: String, url: String)
         .client_builder(
             ClientBuilder::new()
                 .network(network.clone())
-                .primary_node(url.as_str(), None, None)?,
+                .primary_node(config.node_url.as_str(), None, None)?,
         );
     Ok(builder)
 }
@@ -66,7 +86,7 @@ pub async fn create_identity(builder: &mut AccountBuilder) -> Result<Account> {
             Ok(identity)
         },
         Err(err) => {
-            Err(err)
+            Err(err.into())
         }
     }
 }
@@ -74,19 +94,52 @@ pub async fn create_identity(builder: &mut AccountBuilder) -> Result<Account> {
 pub async fn load_identity(builder: &mut AccountBuilder, issuer_did: IotaDID) -> Result<Account> {
     match builder.load_identity(issuer_did).await {
         Ok(issuer) => Ok(issuer),
-        Err(err) => Err(err),
+        Err(err) => Err(err.into()),
     }
 }
 
-pub async fn crea_vc(issuer: &Account, holder: &IotaDID) -> Result<String> {
-    let subject: Subject = Subject::from_json_value(json!({
-    "id": holder,
-    "name": "AccessoSmartContract",
-  }))?;
+/// Fragment of the issuer service holding the revocation bitmap of the issued VCs.
+pub const REVOCATION_SERVICE: &str = "revocation";
+
+/// Adds an empty revocation bitmap service to the issuer document if it has none yet.
+pub async fn ensure_revocation_service(issuer: &mut Account) -> Result<bool> {
+    let exists = issuer.document().service().iter()
+        .any(|service| service.id().fragment() == Some(REVOCATION_SERVICE));
+    if exists {
+        return Ok(false);
+    }
+    issuer
+        .update_identity()
+        .create_service()
+        .fragment(REVOCATION_SERVICE)
+        .type_(RevocationBitmap::TYPE)
+        .endpoint(RevocationBitmap::new().to_endpoint()?)
+        .apply()
+        .await?;
+    Ok(true)
+}
+
+/// Sets the bits of `indexes` in the revocation bitmap and publishes the issuer document.
+pub async fn revoke_vcs(issuer: &mut Account, indexes: &[u32]) -> Result<()> {
+    issuer.revoke_credentials(REVOCATION_SERVICE, indexes).await?;
+    Ok(())
+}
+
+/// Issues a VC granting `claims` to `holder`, revocable through bit `index` of the issuer
+/// revocation bitmap. The credential is valid during the validity window of the claims.
+pub async fn crea_vc(issuer: &Account, holder: &IotaDID, index: u32, claims: &ParticipantClaims) -> Result<String> {
+    let mut subject_json = claims.to_json_value()?;
+    subject_json["id"] = json!(holder);
+    let subject: Subject = Subject::from_json_value(subject_json)?;
+    let service_url = issuer.did().to_url().join(format!("#{}", REVOCATION_SERVICE))?;
+    let status: Status = RevocationBitmapStatus::new(service_url, index).into();
 
     let mut credential: Credential = CredentialBuilder::default()
         .issuer(Url::parse(issuer.did().as_str())?)
-        .type_("AccessoSC")
+        .type_(CREDENTIAL_TYPE)
+        .status(status)
+        .issuance_date(claims.valid_from)
+        .expiration_date(claims.valid_until)
         .subject(subject)
         .build()?;
 
@@ -100,47 +153,59 @@ pub async fn crea_vc(issuer: &Account, holder: &IotaDID) -> Result<String> {
         &CredentialValidationOptions::default(),
         FailFast::FirstError,
     )
-        .unwrap();
+        .map_err(|err| Error::InvalidCredential(err.to_string()))?;
 
     let credential_json: String = credential.to_json()?;
     Ok(credential_json)
 }
 
-pub fn create_challenge() -> (String, Timestamp) {
+/// Random value for a challenge. Its expiry is set by the [`challenge::ChallengeStore`].
+pub fn create_nonce() -> String {
     let mut gen = rand_chacha::ChaCha8Rng::from_entropy();
-    let challenge = gen.next_u64().to_string();
-    let expires: Timestamp = Timestamp::now_utc().checked_add(Duration::minutes(10)).unwrap();
-    (challenge, expires)
+    gen.next_u64().to_string()
 }
 
-pub async fn verify_vp(presentation_json: &String, issuer: &Account, challenge: (String, Timestamp)) -> Result<()> {
+pub async fn verify_vp(config: &Config, presentation_json: &String, issuer: &Account, challenge: (String, Timestamp), holder: &IotaDID) -> Result<()> {
     let presentation: Presentation = Presentation::from_json(&presentation_json)?;
 
+    //The challenge was issued to this DID, so the presentation must come from it
+    match presentation.holder.as_ref() {
+        Some(url) if url.as_str() == holder.as_str() => (),
+        _ => return Err(Error::Rejected(format!("the presentation is not held by {}", holder))),
+    }
+
     let credential: Credential = match presentation.clone().verifiable_credential{
         OneOrMany::One(cre) => cre,
-        OneOrMany::Many(_vec) => return Err(Error::IdentityNotFound),
+        OneOrMany::Many(_vec) => return Err(Error::Rejected(String::from("the presentation holds several credentials"))),
     };
+    //A revoked credential is refused here, with the current bitmap of the issuer
     CredentialValidator::validate(
         &credential,
         &issuer.document(),
-        &CredentialValidationOptions::default(),
+        &CredentialValidationOptions::default().status(StatusCheck::Strict),
         FailFast::FirstError,
     )
-        .unwrap();
+        .map_err(|err| Error::Rejected(format!("invalid credential: {}", err)))?;
+    //Only credentials of the current session, within their validity window, are accepted
+    match ParticipantClaims::from_credential(&credential) {
+        Some(claims) => claims.check_valid_for(config.session_id.as_deref()).map_err(Error::Rejected)?,
+        None => return Err(Error::Rejected(String::from("not a participant credential"))),
+    }
 
     let presentation_verifier_options: VerifierOptions = VerifierOptions::new()
         .challenge(challenge.0.to_owned())
         .allow_expired(false);
 
+    //Credentials are short-lived and renewed by their holders, so one that has not expired yet is enough
     let credential_validation_options: CredentialValidationOptions = CredentialValidationOptions::default()
-        .earliest_expiry_date(Timestamp::now_utc().checked_add(Duration::hours(10)).unwrap());
+        .status(StatusCheck::Strict);
 
     let presentation_validation_options = PresentationValidationOptions::default()
         .presentation_verifier_options(presentation_verifier_options.clone())
         .shared_validation_options(credential_validation_options)
         .subject_holder_relationship(SubjectHolderRelationship::AlwaysSubject);
 
-    let client: Client = match create_client(String::from("dev"), String::from("http://192.168.10.203:14265")).await {
+    let client: Client = match create_client(config).await {
         Ok(client) => client,
         Err(err) => return Err(err),
     };
@@ -161,4 +226,59 @@ pub async fn verify_vp(presentation_json: &String, issuer: &Account, challenge:
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub const DID_CONTROL_TYPE: &str = "FetaDidControlProof";
+
+/// Document a client signs with its `#SCKey` method, over a challenge of the AS, to prove it
+/// controls the DID it asks a VC for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DidControlProof {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub did: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+impl GetSignature for DidControlProof {
+    fn signature(&self) -> Option<&Proof> {
+        self.proof.as_ref()
+    }
+}
+
+impl GetSignatureMut for DidControlProof {
+    fn signature_mut(&mut self) -> Option<&mut Proof> {
+        self.proof.as_mut()
+    }
+}
+
+impl SetSignature for DidControlProof {
+    fn set_signature(&mut self, signature: Proof) {
+        self.proof = Some(signature)
+    }
+}
+
+/// Checks that `proof_json` was signed over `challenge` with the `#SCKey` method of the
+/// published DID document of `holder`.
+pub async fn verify_did_control(config: &Config, proof_json: &str, challenge: (String, Timestamp), holder: &IotaDID) -> Result<()> {
+    let control: DidControlProof = DidControlProof::from_json(proof_json)?;
+    if control.kind != DID_CONTROL_TYPE || control.did != holder.as_str() {
+        return Err(Error::Rejected(format!("not a proof of control of {}", holder)));
+    }
+    match control.proof.as_ref() {
+        Some(proof) if proof.verification_method().ends_with("#SCKey") => (),
+        _ => return Err(Error::Rejected(String::from("the proof is not signed with #SCKey"))),
+    }
+
+    let client: Client = create_client(config).await?;
+    let resolver: Resolver = ResolverBuilder::new().client(Arc::from(client)).build().await?;
+    let document = resolver.resolve(holder).await?.document;
+
+    let verifier_options: VerifierOptions = VerifierOptions::new()
+        .challenge(challenge.0)
+        .allow_expired(false);
+    document.verify_data(&control, &verifier_options)
+        .map_err(|err| Error::Rejected(format!("invalid signature: {}", err)))
+}
@@ -1,143 +1,454 @@
-use std::{io, thread};
-use std::io::{Read, Write};
-use std::io::prelude::*;
-use std::net::{Shutdown, TcpListener, TcpStream};
-use std::str::from_utf8;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
 use identity_iota::account::{Account, AccountBuilder};
-use identity_iota::iota_core::IotaDID;
-use bstr::B;
+use identity_iota::core::Duration;
 use identity_iota::did::DID;
-use tokio::runtime::Runtime;
+use identity_iota::iota_core::IotaDID;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{RwLock, Semaphore};
+use tokio::time;
+use clap::Parser;
+
+use authorizationServer as lib;
+use lib::admission::{AdmissionError, AdmissionPolicy};
+use lib::challenge::{ChallengeError, ChallengeStore};
+use lib::config::{Cli, Config};
+use lib::credential::Participants;
+use lib::protocol::{self, error_frame, ErrorCode, Frame};
+use lib::revocation::RevocationRegistry;
+
+/// How often expired challenges are removed from the store.
+const CHALLENGE_GC_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+/// Everything deciding who gets a credential, and with which claims.
+struct Issuance {
+    registry: RevocationRegistry,
+    participants: Participants,
+    admission: AdmissionPolicy,
+}
+
+async fn reply(stream: &mut TcpStream, frame: Frame) {
+    if let Err(err) = protocol::write_frame(stream, &frame).await {
+        eprintln!("Error: {:?}", err);
+    }
+}
+
+/// Reads the next frame of a client, failing when it stays silent longer than the idle timeout,
+/// so an idle peer cannot hold a session forever.
+async fn read_client_frame(stream: &mut TcpStream, config: &Config) -> io::Result<Frame> {
+    match time::timeout(config.idle_timeout(), protocol::read_frame(stream)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "the client stayed idle for too long")),
+    }
+}
+
+async fn read_did_frame(stream: &mut TcpStream, config: &Config) -> Option<IotaDID> {
+    let did: String = match read_client_frame(stream, config).await {
+        Ok(Frame::Did(did)) => did,
+        Ok(other) => {
+            eprintln!("Error: expected DID, received {:?}", other);
+            reply(stream, error_frame(ErrorCode::BadRequest, "expected a DID frame")).await;
+            return None
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return None
+        },
+    };
+
+    match IotaDID::parse(&did) {
+        Ok(did) => Some(did),
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            reply(stream, error_frame(ErrorCode::InvalidDid, format!("cannot parse {}: {}", did, err))).await;
+            None
+        },
+    }
+}
 
-mod lib;
+/// Issues a VC to `user_did` with the claims of its enrolment, if its credentials were not
+/// revoked and the admission policy accepts it. Replies with the VC or the reason of the refusal.
+async fn issue_vc(stream: &mut TcpStream, config: &Config, issuer: &RwLock<Account>, issuance: &Issuance, user_did: &IotaDID, invite_token: Option<&str>) {
+    let registry = &issuance.registry;
+    if registry.is_revoked(user_did.as_str()) {
+        println!("Refusing a VC to {}, its credentials were revoked", user_did);
+        reply(stream, error_frame(ErrorCode::Forbidden, "the credentials of this DID were revoked")).await;
+        return
+    }
+    let index = match issuance.admission.admit(registry, user_did.as_str(), invite_token) {
+        Ok(index) => index,
+        Err(AdmissionError::Denied(denial)) => {
+            println!("Admission denied to {}: {}", user_did, denial);
+            reply(stream, error_frame(ErrorCode::Forbidden, denial.to_string())).await;
+            return
+        },
+        Err(AdmissionError::Io(err)) => {
+            eprintln!("Error: {:?}", err);
+            reply(stream, error_frame(ErrorCode::IssuerUnavailable, err.to_string())).await;
+            return
+        },
+    };
 
-fn handle_client(mut stream: TcpStream, issuer: Account) {
-    let rt  = Runtime::new().unwrap();
-    let mut data = [0 as u8; 1047]; //1047 byte buffer
+    let claims = issuance.participants.claims_for(user_did.as_str(), config.session_id.as_deref(), Duration::hours(config.credential_lifetime_hours));
+    match lib::crea_vc(&*issuer.read().await, user_did, index, &claims).await {
+        Ok(vc) => {
+            println!("VC created for a {}!", claims.role);
+            reply(stream, Frame::Vc(vc)).await;
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            reply(stream, error_frame(ErrorCode::IssuerUnavailable, err.to_string())).await;
+        },
+    }
+}
+
+/// Outcome of [`check_presentation`] and [`check_did_control`].
+enum Exchange {
+    Verified,
+    /// The client was told why, the session goes on.
+    Rejected,
+    Disconnected,
+}
+
+/// Sends a challenge to `user_did` and verifies the VP it answers with.
+async fn check_presentation(stream: &mut TcpStream, peer: SocketAddr, config: &Config, issuer: &RwLock<Account>, challenges: &ChallengeStore, user_did: &IotaDID) -> Exchange {
+    let challenge = challenges.issue(peer, user_did);
+    println!("Challenge created!");
+    reply(stream, Frame::Challenge(challenge.0.clone())).await;
+    reply(stream, Frame::Expiry(challenge.1.to_rfc3339())).await;
+
+    let vp: String = match read_client_frame(stream, config).await {
+        Ok(Frame::Vp(vp)) => vp,
+        Ok(other) => {
+            eprintln!("Error: expected VP, received {:?}", other);
+            reply(stream, error_frame(ErrorCode::BadRequest, "expected a VP frame")).await;
+            return Exchange::Rejected
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return Exchange::Disconnected
+        },
+    };
+    println!("Received VP from client.");
+
+    let expires = match challenges.redeem(&challenge.0, peer, user_did) {
+        Ok(expires) => expires,
+        Err(err) => {
+            eprintln!("Challenge {} rejected: {}", challenge.0, err);
+            let code = match err {
+                ChallengeError::Expired(_) => ErrorCode::ChallengeExpired,
+                _ => ErrorCode::VpRejected,
+            };
+            reply(stream, error_frame(code, err.to_string())).await;
+            return Exchange::Rejected
+        },
+    };
+
+    match lib::verify_vp(config, &vp, &*issuer.read().await, (challenge.0, expires), user_did).await {
+        Ok(..) => {
+            println!("VP verified!");
+            Exchange::Verified
+        },
+        Err(err) => {
+            eprintln!("Vp not validated: {:?}", err);
+            reply(stream, error_frame(ErrorCode::VpRejected, err.to_string())).await;
+            Exchange::Rejected
+        },
+    }
+}
+
+/// Sends a challenge to `user_did` and verifies that the client signs it with the key of that
+/// DID, before any VC is issued to it.
+async fn check_did_control(stream: &mut TcpStream, peer: SocketAddr, config: &Config, challenges: &ChallengeStore, user_did: &IotaDID) -> Exchange {
+    let challenge = challenges.issue(peer, user_did);
+    reply(stream, Frame::Challenge(challenge.0.clone())).await;
+    reply(stream, Frame::Expiry(challenge.1.to_rfc3339())).await;
+
+    let proof: String = match read_client_frame(stream, config).await {
+        Ok(Frame::Proof(proof)) => proof,
+        Ok(other) => {
+            eprintln!("Error: expected proof of DID control, received {:?}", other);
+            reply(stream, error_frame(ErrorCode::BadRequest, "expected a proof frame")).await;
+            return Exchange::Rejected
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return Exchange::Disconnected
+        },
+    };
+
+    let expires = match challenges.redeem(&challenge.0, peer, user_did) {
+        Ok(expires) => expires,
+        Err(err) => {
+            eprintln!("Challenge {} rejected: {}", challenge.0, err);
+            let code = match err {
+                ChallengeError::Expired(_) => ErrorCode::ChallengeExpired,
+                _ => ErrorCode::ProofRejected,
+            };
+            reply(stream, error_frame(code, err.to_string())).await;
+            return Exchange::Rejected
+        },
+    };
+
+    match lib::verify_did_control(config, &proof, (challenge.0, expires), user_did).await {
+        Ok(..) => {
+            println!("Control of {} proven", user_did);
+            Exchange::Verified
+        },
+        Err(err) => {
+            println!("Refusing a VC to {}, control of the DID not proven: {}", user_did, err);
+            reply(stream, error_frame(ErrorCode::ProofRejected, err.to_string())).await;
+            Exchange::Rejected
+        },
+    }
+}
+
+async fn handle_client(mut stream: TcpStream, peer: SocketAddr, config: Arc<Config>, issuer: Arc<RwLock<Account>>, challenges: Arc<ChallengeStore>, issuance: Arc<Issuance>) {
     let mut iteration = 0;
-    'foo: while match stream.read(&mut data) {
-        Ok(size) => {
-            let msg = from_utf8(&data[0..size]).unwrap();
-            println!("\nReceived instruction: {}", msg);
-
-            match msg {
-                "vc" => {
-                    stream.write(b"ack").unwrap();
-                    match stream.read(&mut data) {
-                        Ok(size) => {
-                            let did = from_utf8(&data[0..size]).unwrap();
-
-                            let user_did: IotaDID = match IotaDID::parse(did) {
-                                Ok(did) => did,
-                                Err(err) => {
-                                    eprintln!("Error: {:?}", err);
-                                    return
-                                },
-                            };
-
-                            rt.block_on(async {
-                                let vc: String = match lib::crea_vc(&issuer, &user_did).await {
-                                    Ok(vc) => vc,
-                                    Err(err) => {
-                                        eprintln!("Error: {:?}", err);
-                                        return
-                                    }
-                                };
-                                println!("VC created!");
-                                stream.write(B(vc.as_str())).unwrap();
-                            });
-                        }
-                        Err(err) => {
-                            eprintln!("Error: {:?}", err);
-                            return
-                        }
-                    }
-                },
-                "vp" => {
-                    let challenge = lib::create_challenge();
-                    println!("Challenge created!");
-                    stream.write(B(challenge.0.as_str())).unwrap();
-
-                    match stream.read(&mut data) {
-                        Ok(..) => println!(),
-                        Err(err) => {
-                            eprintln!("Error: {:?}", err);
-                            return
-                        },
-                    };
-
-                    stream.write(B(challenge.1.to_rfc3339().as_str())).unwrap();
-
-                    let vp: &str = match stream.read(&mut data) {
-                        Ok(size) => from_utf8(&data[0..size]).unwrap(),
-                        Err(err) => {
-                            eprintln!("Error: {:?}", err);
-                            return
-                        },
-                    };
-                    println!("Received VP from client.");
-
-                    rt.block_on(async {
-                        match lib::verify_vp(&String::from(vp), &issuer, challenge).await {
-                            Ok(..) => {
-                                println!("VP verified!");
-                                stream.write(B(&issuer.did().as_str())).unwrap();
-                            },
-                            Err(err) => {
-                                eprintln!("Vp not validated: {:?}", err);
-                                return
-                            },
-                        };
-                    });
-                },
-                "shutdown" => {
-                    println!("Terminating connection with {}", stream.peer_addr().unwrap());
-                    break 'foo
-                },
-                _ => {
-                    println!("\nUnknown input received");
-                    iteration += 1;
-                    if iteration == 10 {
-                        return
-                    }
-                },
+    loop {
+        let frame = match read_client_frame(&mut stream, &config).await {
+            Ok(frame) => frame,
+            Err(err) => {
+                println!("Terminating connection with {}: {}", peer, err);
+                break
             }
-            true
-        },
-        Err(_) => {
-            println!("An error occurred, terminating connection with {}", stream.peer_addr().unwrap());
-            stream.shutdown(Shutdown::Both).unwrap();
-            false
+        };
+        println!("\nReceived instruction from {}: {:?}", peer, frame);
+
+        match frame {
+            Frame::Command(cmd) if cmd == "vc" => {
+                let user_did: IotaDID = match read_did_frame(&mut stream, &config).await {
+                    Some(did) => did,
+                    None => continue,
+                };
+                let invite_token: String = match read_client_frame(&mut stream, &config).await {
+                    Ok(Frame::Invite(token)) => token,
+                    Ok(other) => {
+                        eprintln!("Error: expected invite token, received {:?}", other);
+                        reply(&mut stream, error_frame(ErrorCode::BadRequest, "expected an invite frame")).await;
+                        continue
+                    },
+                    Err(err) => {
+                        eprintln!("Error: {:?}", err);
+                        break
+                    },
+                };
+                //The requester must hold the keys of the DID before it is admitted
+                match check_did_control(&mut stream, peer, &config, &challenges, &user_did).await {
+                    Exchange::Verified => issue_vc(&mut stream, &config, &issuer, &issuance, &user_did, Some(&invite_token)).await,
+                    Exchange::Rejected => continue,
+                    Exchange::Disconnected => break,
+                }
+            },
+            Frame::Command(cmd) if cmd == "vp" => {
+                let user_did: IotaDID = match read_did_frame(&mut stream, &config).await {
+                    Some(did) => did,
+                    None => continue,
+                };
+                match check_presentation(&mut stream, peer, &config, &issuer, &challenges, &user_did).await {
+                    Exchange::Verified => {
+                        let issuer_did = issuer.read().await.did().to_string();
+                        reply(&mut stream, Frame::Result(issuer_did)).await;
+                    },
+                    Exchange::Rejected => continue,
+                    Exchange::Disconnected => break,
+                }
+            },
+            //A holder proves it still holds a valid VC and receives a new one
+            Frame::Command(cmd) if cmd == "renew" => {
+                let user_did: IotaDID = match read_did_frame(&mut stream, &config).await {
+                    Some(did) => did,
+                    None => continue,
+                };
+                match check_presentation(&mut stream, peer, &config, &issuer, &challenges, &user_did).await {
+                    Exchange::Verified => issue_vc(&mut stream, &config, &issuer, &issuance, &user_did, None).await,
+                    Exchange::Rejected => continue,
+                    Exchange::Disconnected => break,
+                }
+            },
+            //Administrative, only accepted from the machine the AS runs on
+            Frame::Command(cmd) if cmd == "revoke" => {
+                if !peer.ip().is_loopback() {
+                    println!("Refusing a revocation requested by {}", peer);
+                    reply(&mut stream, error_frame(ErrorCode::Forbidden, "revocations are only accepted from localhost")).await;
+                    continue
+                }
+                let user_did: IotaDID = match read_did_frame(&mut stream, &config).await {
+                    Some(did) => did,
+                    None => continue,
+                };
+
+                let revocation = match issuance.registry.revoke(user_did.as_str()) {
+                    Ok(revocation) => revocation,
+                    Err(err) => {
+                        eprintln!("Error: {:?}", err);
+                        reply(&mut stream, error_frame(ErrorCode::IssuerUnavailable, err.to_string())).await;
+                        continue
+                    },
+                };
+                let indexes = revocation.indexes;
+                if indexes.is_empty() {
+                    reply(&mut stream, error_frame(ErrorCode::BadRequest, format!("no credential was issued to {}", user_did))).await;
+                    continue
+                }
+
+                match lib::revoke_vcs(&mut *issuer.write().await, &indexes).await {
+                    Ok(..) => {
+                        println!("Revoked {} credentials of {}", indexes.len(), user_did);
+                        reply(&mut stream, Frame::Result(format!("revoked {} credentials of {}", indexes.len(), user_did))).await;
+                    },
+                    Err(err) => {
+                        eprintln!("Error: {:?}", err);
+                        //A holder revoked by an earlier request stays revoked
+                        if revocation.newly_revoked {
+                            if let Err(err) = issuance.registry.restore(user_did.as_str()) {
+                                eprintln!("Error: {:?}", err);
+                            }
+                        }
+                        reply(&mut stream, error_frame(ErrorCode::IssuerUnavailable, err.to_string())).await;
+                    },
+                }
+            },
+            Frame::Command(cmd) if cmd == "shutdown" => {
+                println!("Terminating connection with {}", peer);
+                break
+            },
+            _ => {
+                println!("\nUnknown input received");
+                reply(&mut stream, error_frame(ErrorCode::BadRequest, "unknown command")).await;
+                iteration += 1;
+                if iteration == 10 {
+                    break
+                }
+            },
         }
-    } {}
-    stream.shutdown(Shutdown::Both).unwrap();
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Sends a revocation to the AS listening on this machine and prints the outcome.
+async fn request_revocation(config: &Config, did: &str) {
+    let mut addr: SocketAddr = match config.listen_addr.parse() {
+        Ok(addr) => addr,
+        Err(err) => {
+            eprintln!("Error: cannot parse {}: {:?}", config.listen_addr, err);
+            return
+        },
+    };
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+
+    let mut stream = match TcpStream::connect(addr).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("Error: cannot reach the AS on {}: {:?}", addr, err);
+            return
+        },
+    };
+    let _ = protocol::write_frame(&mut stream, &Frame::Command(String::from("revoke"))).await;
+    let _ = protocol::write_frame(&mut stream, &Frame::Did(did.to_string())).await;
+    match protocol::read_frame(&mut stream).await {
+        Ok(Frame::Result(result)) => println!("{}", result),
+        Ok(Frame::Error { code, message }) => eprintln!("Revocation refused ({}): {}", code, message),
+        Ok(other) => eprintln!("Error: unexpected answer {:?}", other),
+        Err(err) => eprintln!("Error: {:?}", err),
+    }
+    let _ = protocol::write_frame(&mut stream, &Frame::Command(String::from("shutdown"))).await;
 }
 
 #[tokio::main]
 async fn main() {
-    // println!("Insert Stronghold password:");
-    // println!("If the stronghold does not exists a new one will be created with the password of your choice");
-    // let stdin = io::stdin();
-    let password = String::from("");
+    let cli = Cli::parse();
+    let config: Arc<Config> = match Config::load(&cli) {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("Error: cannot load configuration: {}", err);
+            return
+        },
+    };
+
+    if let Some(did) = &cli.revoke {
+        request_revocation(&config, did).await;
+        return
+    }
+
+    let password: String = match config.stronghold_password() {
+        Ok(password) => password,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return
+        },
+    };
 
+    let rotate = cli.rotate_identity;
+    let existing = lib::identity_exists(&config);
 
-    let mut builder: AccountBuilder = match lib::create_builder(password, String::from("dev"), String::from("http://192.168.10.203:14265")).await {
+
+    let mut builder: AccountBuilder = match lib::create_builder(password, &config).await {
         Ok(res) => {
             println!("\nBuilder created!");
             res
         },
+        Err(err) if config.path("strong.hodl").exists() => {
+            eprintln!("Error: cannot open the Stronghold at {}, the password is wrong or the snapshot is damaged: {:?}", config.path("strong.hodl").display(), err);
+            return
+        },
         Err(err) => {
             eprintln!("Error: {:?}", err);
             return
         },
     };
 
-    //SERVER IDENTITY GENERATION, UNCOMMENT TO USE
-    //---------------------------------------------------------------------------------------------
-    
-    let issuer: Account = match lib::create_identity(&mut builder).await {
+    //The issuer identity is created on first boot only, unless a rotation is requested:
+    //every VC issued so far is bound to the current DID.
+    if rotate || !existing {
+        let issuer: Account = match lib::create_identity(&mut builder).await {
+            Ok(identity) => {
+                println!("Identity created! DID: {}", identity.did());
+                identity
+            },
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return
+            },
+        };
+        match lib::write_did(&config, issuer.did()) {
+            Ok(..) => println!("Did saved in did.txt"),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return
+            },
+        }
+        // Release the identity so it can be loaded once below and shared by all sessions.
+        drop(issuer);
+    } else {
+        println!("Found an existing identity, loading it");
+    }
+
+    let did: String = match lib::read_did(&config) {
+        Ok(did) => did,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return
+        },
+    };
+    let issuer_did: IotaDID = match IotaDID::parse(did) {
+        Ok(did) => did,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return
+        },
+    };
+
+    let mut issuer: Account = match lib::load_identity(&mut builder, issuer_did).await {
         Ok(identity) => {
-            println!("Identity created! DID: {}", identity.did());
+            println!("Identity loaded! DID: {}", identity.did());
             identity
         },
         Err(err) => {
@@ -145,52 +456,92 @@ async fn main() {
             return
         },
     };
-    match lib::write_did(issuer.did()) {
-        Ok(..) => println!("Did saved in did.txt"),
+    match lib::ensure_revocation_service(&mut issuer).await {
+        Ok(true) => println!("Revocation bitmap published in the issuer document"),
+        Ok(false) => (),
         Err(err) => {
             eprintln!("Error: {:?}", err);
             return
         },
     }
-    
-    //---------------------------------------------------------------------------------------------
+    let issuer: Arc<RwLock<Account>> = Arc::new(RwLock::new(issuer));
 
-    let did: String = match lib::read_did() {
-        Ok(did) => did,
+    let registry = match RevocationRegistry::load(config.path("revocation.toml")) {
+        Ok(registry) => registry,
         Err(err) => {
-            eprintln!("Error: {:?}", err);
+            eprintln!("Error: cannot load the revocation registry: {:?}", err);
             return
         },
     };
-    let issuer_did: IotaDID = match IotaDID::parse(did) {
-        Ok(did) => did,
+    let participants = match Participants::load(config.participants_file.as_deref(), config.default_role) {
+        Ok(participants) => participants,
         Err(err) => {
-            eprintln!("Error: {:?}", err);
+            eprintln!("Error: cannot load the participants file: {:?}", err);
+            return
+        },
+    };
+    let admission = match AdmissionPolicy::from_config(&config) {
+        Ok(admission) => admission,
+        Err(err) => {
+            eprintln!("Error: cannot load the admission policy: {:?}", err);
             return
         },
     };
+    let rules = admission.rules();
+    if rules.is_empty() {
+        println!("Warning: no admission rule configured, any DID that reaches the AS obtains a VC");
+    } else {
+        println!("Admission rules: {}", rules.join(", "));
+    }
+    let issuance = Arc::new(Issuance { registry, participants, admission });
+    let sessions = Arc::new(Semaphore::new(config.max_sessions));
+
+    let challenges = Arc::new(ChallengeStore::new(Duration::minutes(config.challenge_ttl_minutes)));
+    let gc_challenges = challenges.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(CHALLENGE_GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            let removed = gc_challenges.purge_expired();
+            if removed > 0 {
+                println!("Removed {} expired challenges", removed);
+            }
+        }
+    });
 
-    let listener = TcpListener::bind("0.0.0.0:3333").unwrap();
-    println!("\nServer listening on port 3333");
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                println!("New connection: {}", stream.peer_addr().unwrap());
+    let listener = match TcpListener::bind(&config.listen_addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Error: cannot listen on {}: {:?}", config.listen_addr, err);
+            return
+        },
+    };
+    println!("\nServer listening on {}", config.listen_addr);
+    loop {
+        match listener.accept().await {
+            Ok((mut stream, peer)) => {
+                println!("New connection: {}", peer);
 
-                let issuer: Account = match lib::load_identity(&mut builder, issuer_did.clone()).await {
-                    Ok(identity) => {
-                        println!("Identity loaded! DID: {}", identity.did());
-                        identity
-                    },
-                    Err(err) => {
-                        eprintln!("Error: {:?}", err);
-                        return
+                //Past the limit the client is told so, instead of waiting for a free session
+                let permit = match sessions.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        println!("Refusing {}, {} sessions are already open", peer, config.max_sessions);
+                        tokio::spawn(async move {
+                            reply(&mut stream, error_frame(ErrorCode::Busy, "too many open sessions, retry later")).await;
+                            let _ = stream.shutdown().await;
+                        });
+                        continue
                     },
                 };
 
-                thread::spawn(move|| {
-                    //connection succeeded
-                    handle_client(stream, issuer)
+                let config = config.clone();
+                let issuer = issuer.clone();
+                let challenges = challenges.clone();
+                let issuance = issuance.clone();
+                tokio::spawn(async move {
+                    handle_client(stream, peer, config, issuer, challenges, issuance).await;
+                    drop(permit);
                 });
             }
             Err(e) => {
@@ -199,6 +550,4 @@ async fn main() {
             }
         }
     }
-    // close the socket server
-    drop(listener);
-}
\ No newline at end of file
+}
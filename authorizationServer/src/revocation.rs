@@ -0,0 +1,102 @@
+//! Indexes of the issued credentials in the revocation bitmap of the issuer, persisted in the
+//! data directory so that revocations survive a restart.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct RegistryState {
+    next_index: u32,
+    /// Bitmap indexes of the credentials issued to each holder DID.
+    issued: BTreeMap<String, Vec<u32>>,
+    /// Holders whose credentials were revoked, they get no new one.
+    revoked: BTreeSet<String>,
+}
+
+/// Outcome of [`RevocationRegistry::revoke`].
+pub struct Revocation {
+    /// Bitmap indexes of the credentials of the holder, empty when none was issued.
+    pub indexes: Vec<u32>,
+    /// False when the holder was already revoked, a failed publication must then leave it revoked.
+    pub newly_revoked: bool,
+}
+
+pub struct RevocationRegistry {
+    path: PathBuf,
+    state: Mutex<RegistryState>,
+}
+
+impl RevocationRegistry {
+    /// Loads the registry at `path`, or starts an empty one if the file does not exist yet.
+    pub fn load(path: PathBuf) -> io::Result<RevocationRegistry> {
+        let state = match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => RegistryState::default(),
+            Err(err) => return Err(err),
+        };
+        Ok(RevocationRegistry { path, state: Mutex::new(state) })
+    }
+
+    pub fn is_revoked(&self, holder: &str) -> bool {
+        self.state.lock().unwrap().revoked.contains(holder)
+    }
+
+    /// True when a credential was ever issued to `holder`.
+    pub fn has_issued(&self, holder: &str) -> bool {
+        self.state.lock().unwrap().issued.contains_key(holder)
+    }
+
+    /// Number of holders with credentials that were not revoked.
+    pub fn active_holders(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.issued.keys().filter(|holder| !state.revoked.contains(*holder)).count()
+    }
+
+    /// Reserves the bitmap index of a new credential for `holder`.
+    pub fn allocate(&self, holder: &str) -> io::Result<u32> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.next_index;
+        state.next_index = index
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("no revocation index left"))?;
+        state.issued.entry(holder.to_string()).or_default().push(index);
+        self.save(&state)?;
+        Ok(index)
+    }
+
+    /// Marks `holder` as revoked and returns the indexes to set in the bitmap.
+    pub fn revoke(&self, holder: &str) -> io::Result<Revocation> {
+        let mut state = self.state.lock().unwrap();
+        let indexes = match state.issued.get(holder) {
+            Some(indexes) => indexes.clone(),
+            None => return Ok(Revocation { indexes: Vec::new(), newly_revoked: false }),
+        };
+        let newly_revoked = state.revoked.insert(holder.to_string());
+        if newly_revoked {
+            self.save(&state)?;
+        }
+        Ok(Revocation { indexes, newly_revoked })
+    }
+
+    /// Undoes a [`RevocationRegistry::revoke`] that newly revoked `holder`, when the bitmap
+    /// could not be published.
+    pub fn restore(&self, holder: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.revoked.remove(holder);
+        self.save(&state)
+    }
+
+    fn save(&self, state: &RegistryState) -> io::Result<()> {
+        let content = toml::to_string(state).map_err(io::Error::other)?;
+        //Write aside and rename, so a crash never leaves a truncated registry
+        let tmp = self.path.with_extension("toml.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
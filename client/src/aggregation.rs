@@ -0,0 +1,409 @@
+//! Aggregation of the verified models of a round into the next global model.
+//!
+//! Signatures prove who sent a model, not that it was honestly trained, so besides federated
+//! averaging a client can use one of the Byzantine-robust aggregators below.
+
+use std::fmt;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::model::{Model, Tensor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Aggregator {
+    /// Mean weighted by the declared sample counts.
+    #[default]
+    #[serde(rename = "fedavg")]
+    #[value(name = "fedavg")]
+    FedAvg,
+    /// Coordinate-wise median.
+    Median,
+    /// Coordinate-wise mean after dropping the largest and smallest values.
+    TrimmedMean,
+    /// The single model closest to its neighbours.
+    Krum,
+    /// FedAvg over the models with the best Krum scores.
+    MultiKrum,
+}
+
+impl fmt::Display for Aggregator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregator::FedAvg => write!(f, "fedavg"),
+            Aggregator::Median => write!(f, "median"),
+            Aggregator::TrimmedMean => write!(f, "trimmed-mean"),
+            Aggregator::Krum => write!(f, "krum"),
+            Aggregator::MultiKrum => write!(f, "multi-krum"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregationOptions {
+    pub aggregator: Aggregator,
+    /// Fraction of the values dropped at each end by the trimmed mean.
+    pub trim_ratio: f64,
+    /// Number of Byzantine clients Krum and Multi-Krum tolerate.
+    pub byzantine: usize,
+    /// Largest L2 distance a model may have from the reference before it is scaled down.
+    pub clip_norm: Option<f64>,
+}
+
+impl AggregationOptions {
+    pub fn from_config(config: &Config) -> AggregationOptions {
+        AggregationOptions {
+            aggregator: config.aggregator,
+            trim_ratio: config.trim_ratio,
+            byzantine: config.byzantine,
+            clip_norm: config.clip_norm,
+        }
+    }
+}
+
+/// Combines the models of a round with the configured aggregator.
+///
+/// `reference` is the global model of the previous round. Norm clipping is measured from it,
+/// or from the coordinate-wise median of the round when there is no previous model yet.
+pub fn aggregate(models: &[Model], reference: Option<&Model>, options: &AggregationOptions) -> Result<Model> {
+    let first = match models.first() {
+        Some(first) => first,
+        None => return Err(Error::Aggregation(String::from("no models to aggregate"))),
+    };
+    for model in models {
+        check_layout(first, model)?;
+    }
+
+    let clipped;
+    let models = match options.clip_norm {
+        Some(clip_norm) => {
+            let center = match reference {
+                Some(reference) => {
+                    check_layout(first, reference)?;
+                    reference.clone()
+                },
+                None => median(models),
+            };
+            clipped = models.iter().map(|model| clip(model, &center, clip_norm)).collect::<Vec<Model>>();
+            &clipped[..]
+        },
+        None => models,
+    };
+
+    match options.aggregator {
+        Aggregator::FedAvg => fedavg(models),
+        Aggregator::Median => Ok(median(models)),
+        Aggregator::TrimmedMean => trimmed_mean(models, options.trim_ratio),
+        Aggregator::Krum => {
+            let best = krum_ranking(models, options.byzantine)?[0];
+            Ok(models[best].clone())
+        },
+        Aggregator::MultiKrum => {
+            //The ranking checks n >= 2f + 3 first, so the subtraction cannot underflow
+            let ranking = krum_ranking(models, options.byzantine)?;
+            let selected = models.len() - options.byzantine;
+            let best: Vec<Model> = ranking[..selected].iter().map(|&i| models[i].clone()).collect();
+            fedavg(&best)
+        },
+    }
+}
+
+/// Federated averaging: every tensor of the global model is the mean of the participants'
+/// tensors, weighted by the number of samples each participant declared.
+///
+/// When some model does not declare a sample count, all models get the same weight.
+pub fn fedavg(models: &[Model]) -> Result<Model> {
+    let first = match models.first() {
+        Some(first) => first,
+        None => return Err(Error::Aggregation(String::from("no models to aggregate"))),
+    };
+    for model in models {
+        check_layout(first, model)?;
+    }
+
+    let weights: Vec<f64> = if models.iter().all(|model| model.num_samples.is_some()) {
+        models.iter().map(|model| model.num_samples.unwrap() as f64).collect()
+    } else {
+        vec![1.0; models.len()]
+    };
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return Err(Error::Aggregation(String::from("the declared sample counts sum to zero")));
+    }
+
+    let mut tensors = Vec::with_capacity(first.tensors.len());
+    for (position, layer) in first.tensors.iter().enumerate() {
+        //Accumulate in f64 so that many small contributions are not lost
+        let mut sum = vec![0f64; layer.data.len()];
+        for (model, weight) in models.iter().zip(&weights) {
+            for (acc, value) in sum.iter_mut().zip(&model.tensors[position].data) {
+                *acc += *value as f64 * weight;
+            }
+        }
+        tensors.push(Tensor {
+            name: layer.name.clone(),
+            shape: layer.shape.clone(),
+            data: sum.into_iter().map(|value| (value / total) as f32).collect(),
+        });
+    }
+
+    Ok(Model {
+        tensors,
+        num_samples: total_samples(models),
+    })
+}
+
+/// Coordinate-wise median. Sample counts are ignored, a client cannot buy influence by
+/// declaring a large dataset.
+pub fn median(models: &[Model]) -> Model {
+    per_coordinate(models, |values| {
+        let middle = values.len() / 2;
+        if values.len() % 2 == 0 {
+            (values[middle - 1] + values[middle]) / 2.0
+        } else {
+            values[middle]
+        }
+    })
+}
+
+/// Coordinate-wise mean of the values left after dropping `trim_ratio` of them at each end.
+pub fn trimmed_mean(models: &[Model], trim_ratio: f64) -> Result<Model> {
+    if !(0.0..0.5).contains(&trim_ratio) {
+        return Err(Error::Aggregation(format!("the trim ratio must be in [0, 0.5), found {}", trim_ratio)));
+    }
+    let trimmed = (models.len() as f64 * trim_ratio).floor() as usize;
+    Ok(per_coordinate(models, |values| {
+        let kept = &values[trimmed..values.len() - trimmed];
+        kept.iter().sum::<f64>() / kept.len() as f64
+    }))
+}
+
+/// Indexes of the models sorted by Krum score, best first. The score of a model is the sum
+/// of the squared distances to its `n - f - 2` closest neighbours.
+pub fn krum_ranking(models: &[Model], byzantine: usize) -> Result<Vec<usize>> {
+    let n = models.len();
+    if n < 2 * byzantine + 3 {
+        return Err(Error::Aggregation(format!(
+            "Krum needs at least {} models to tolerate {} Byzantine clients, found {}", 2 * byzantine + 3, byzantine, n
+        )));
+    }
+    let neighbours = n - byzantine - 2;
+
+    let mut distances = vec![vec![0f64; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let distance = squared_distance(&models[i], &models[j]);
+            distances[i][j] = distance;
+            distances[j][i] = distance;
+        }
+    }
+
+    let scores: Vec<f64> = (0..n)
+        .map(|i| {
+            let mut others: Vec<f64> = (0..n).filter(|&j| j != i).map(|j| distances[i][j]).collect();
+            others.sort_by(f64::total_cmp);
+            others[..neighbours].iter().sum()
+        })
+        .collect();
+
+    let mut ranking: Vec<usize> = (0..n).collect();
+    ranking.sort_by(|&left, &right| scores[left].total_cmp(&scores[right]));
+    Ok(ranking)
+}
+
+/// Scales the difference between `model` and `center` down to at most `clip_norm`.
+pub fn clip(model: &Model, center: &Model, clip_norm: f64) -> Model {
+    let norm = squared_distance(model, center).sqrt();
+    if norm <= clip_norm {
+        return model.clone();
+    }
+    let scale = clip_norm / norm;
+
+    let tensors = model.tensors.iter().zip(&center.tensors)
+        .map(|(tensor, center)| Tensor {
+            name: tensor.name.clone(),
+            shape: tensor.shape.clone(),
+            data: tensor.data.iter().zip(&center.data)
+                .map(|(value, center)| (*center as f64 + (*value as f64 - *center as f64) * scale) as f32)
+                .collect(),
+        })
+        .collect();
+    Model { tensors, num_samples: model.num_samples }
+}
+
+fn squared_distance(left: &Model, right: &Model) -> f64 {
+    left.tensors.iter().zip(&right.tensors)
+        .flat_map(|(left, right)| left.data.iter().zip(&right.data))
+        .map(|(left, right)| (*left as f64 - *right as f64).powi(2))
+        .sum()
+}
+
+/// Builds a model whose every value is `combine` applied to the sorted values of the
+/// participants at the same coordinate.
+fn per_coordinate<F: Fn(&[f64]) -> f64>(models: &[Model], combine: F) -> Model {
+    let first = &models[0];
+    let mut values = vec![0f64; models.len()];
+
+    let tensors = first.tensors.iter().enumerate()
+        .map(|(position, layer)| {
+            let data = (0..layer.data.len())
+                .map(|coordinate| {
+                    for (slot, model) in values.iter_mut().zip(models) {
+                        *slot = model.tensors[position].data[coordinate] as f64;
+                    }
+                    values.sort_by(f64::total_cmp);
+                    combine(&values) as f32
+                })
+                .collect();
+            Tensor { name: layer.name.clone(), shape: layer.shape.clone(), data }
+        })
+        .collect();
+    Model { tensors, num_samples: total_samples(models) }
+}
+
+/// Samples behind the aggregated model, `None` when a model does not declare its count.
+/// Counts come from other clients, so the sum saturates instead of overflowing.
+fn total_samples(models: &[Model]) -> Option<u64> {
+    models.iter().try_fold(0u64, |total, model| Some(total.saturating_add(model.num_samples?)))
+}
+
+/// Models can only be combined when they have the same tensors with the same shapes.
+fn check_layout(reference: &Model, model: &Model) -> Result<()> {
+    if reference.tensors.len() != model.tensors.len() {
+        return Err(Error::Aggregation(format!(
+            "expected {} tensors, found {}", reference.tensors.len(), model.tensors.len()
+        )));
+    }
+    for (expected, found) in reference.tensors.iter().zip(&model.tensors) {
+        if expected.name != found.name || expected.shape != found.shape {
+            return Err(Error::Aggregation(format!(
+                "tensor {} {:?} does not match {} {:?}", found.name, found.shape, expected.name, expected.shape
+            )));
+        }
+        if found.data.len() != found.shape.iter().product::<usize>() {
+            return Err(Error::Aggregation(format!("tensor {} has the wrong number of values", found.name)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(values: &[f32], num_samples: Option<u64>) -> Model {
+        let tensor = Tensor { name: Model::tensor_name(0), shape: vec![values.len()], data: values.to_vec() };
+        Model { tensors: vec![tensor], num_samples }
+    }
+
+    fn models(values: &[f32]) -> Vec<Model> {
+        values.iter().map(|value| model(&[*value], None)).collect()
+    }
+
+    fn options(aggregator: Aggregator, byzantine: usize) -> AggregationOptions {
+        AggregationOptions { aggregator, trim_ratio: 0.0, byzantine, clip_norm: None }
+    }
+
+    fn assert_values(model: &Model, expected: &[f32]) {
+        let found = &model.tensors[0].data;
+        assert_eq!(found.len(), expected.len());
+        for (found, expected) in found.iter().zip(expected) {
+            assert!((found - expected).abs() < 1e-6, "expected {:?}, found {:?}", expected, found);
+        }
+    }
+
+    #[test]
+    fn krum_ranks_by_distance_to_closest_neighbours() {
+        //n = 5 and f = 1, so each score sums the 2 smallest squared distances:
+        //0 -> 1 + 4, 1 -> 1 + 1, 2 -> 1 + 4, 10 -> 1 + 64, 11 -> 1 + 81
+        let ranking = krum_ranking(&models(&[0.0, 1.0, 2.0, 10.0, 11.0]), 1).unwrap();
+        assert_eq!(ranking, vec![1, 0, 2, 3, 4]);
+    }
+
+    #[test]
+    fn krum_keeps_the_best_model() {
+        let global = aggregate(&models(&[0.0, 1.0, 2.0, 10.0, 11.0]), None, &options(Aggregator::Krum, 1)).unwrap();
+        assert_values(&global, &[1.0]);
+    }
+
+    #[test]
+    fn krum_needs_2f_plus_3_models() {
+        assert!(krum_ranking(&models(&[0.0, 1.0, 2.0, 3.0]), 1).is_err());
+        assert!(krum_ranking(&models(&[0.0, 1.0, 2.0, 3.0, 4.0]), 1).is_ok());
+    }
+
+    #[test]
+    fn multi_krum_averages_the_n_minus_f_best_models() {
+        //The worst score is the one of 11, the others average to (1 + 0 + 2 + 10) / 4
+        let global = aggregate(&models(&[0.0, 1.0, 2.0, 10.0, 11.0]), None, &options(Aggregator::MultiKrum, 1)).unwrap();
+        assert_values(&global, &[3.25]);
+    }
+
+    #[test]
+    fn multi_krum_refuses_more_byzantine_clients_than_models() {
+        assert!(aggregate(&models(&[0.0, 1.0, 2.0]), None, &options(Aggregator::MultiKrum, 5)).is_err());
+    }
+
+    #[test]
+    fn trimmed_mean_drops_both_ends() {
+        let round = models(&[1.0, 2.0, 3.0, 4.0, 100.0]);
+        //floor(5 * 0.2) = 1 value dropped at each end
+        assert_values(&trimmed_mean(&round, 0.2).unwrap(), &[3.0]);
+        //floor(5 * 0.19) = 0, nothing is dropped
+        assert_values(&trimmed_mean(&round, 0.19).unwrap(), &[22.0]);
+        assert_values(&trimmed_mean(&round, 0.0).unwrap(), &[22.0]);
+    }
+
+    #[test]
+    fn trimmed_mean_rejects_ratios_out_of_bounds() {
+        let round = models(&[1.0, 2.0, 3.0]);
+        assert!(trimmed_mean(&round, 0.5).is_err());
+        assert!(trimmed_mean(&round, -0.1).is_err());
+        assert!(trimmed_mean(&round, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn median_of_even_count_is_the_mean_of_the_middle_values() {
+        assert_values(&median(&models(&[4.0, 1.0, 3.0, 2.0])), &[2.5]);
+        assert_values(&median(&models(&[5.0, 1.0, 3.0])), &[3.0]);
+    }
+
+    #[test]
+    fn median_is_per_coordinate() {
+        let round = vec![model(&[1.0, 30.0], None), model(&[2.0, 10.0], None), model(&[3.0, 20.0], None)];
+        assert_values(&median(&round), &[2.0, 20.0]);
+    }
+
+    #[test]
+    fn clip_scales_down_to_the_norm() {
+        let center = model(&[0.0, 0.0], None);
+        //|(3, 4)| = 5, scaled by 1 / 5
+        assert_values(&clip(&model(&[3.0, 4.0], None), &center, 1.0), &[0.6, 0.8]);
+        assert_values(&clip(&model(&[3.0, 4.0], None), &center, 5.0), &[3.0, 4.0]);
+    }
+
+    #[test]
+    fn clip_is_measured_from_the_center() {
+        let center = model(&[1.0, 1.0], None);
+        assert_values(&clip(&model(&[4.0, 5.0], Some(7)), &center, 2.5), &[2.5, 3.0]);
+        assert_eq!(clip(&model(&[4.0, 5.0], Some(7)), &center, 2.5).num_samples, Some(7));
+    }
+
+    #[test]
+    fn fedavg_weights_by_samples() {
+        let round = vec![model(&[0.0], Some(1)), model(&[4.0], Some(3))];
+        let global = fedavg(&round).unwrap();
+        assert_values(&global, &[3.0]);
+        assert_eq!(global.num_samples, Some(4));
+    }
+
+    #[test]
+    fn total_samples_saturates() {
+        let round = vec![model(&[0.0], Some(u64::MAX)), model(&[0.0], Some(1))];
+        assert_eq!(total_samples(&round), Some(u64::MAX));
+        let round = vec![model(&[0.0], Some(1)), model(&[0.0], None)];
+        assert_eq!(total_samples(&round), None);
+    }
+}
@@ -0,0 +1,329 @@
+//! Deployment settings of a federated learning client.
+//!
+//! Values come from the built-in defaults, then an optional TOML file, then environment
+//! variables and command line flags (the last two are handled together by clap).
+
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::aggregation::Aggregator;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Parser)]
+#[command(name = "client", about = "Federated learning client on the IOTA Tangle")]
+pub struct Cli {
+    /// TOML configuration file.
+    #[arg(long, env = "FETA_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// IOTA network name.
+    #[arg(long, env = "FETA_NETWORK")]
+    pub network: Option<String>,
+
+    /// URL of the Tangle node.
+    #[arg(long, env = "FETA_NODE_URL")]
+    pub node_url: Option<String>,
+
+    /// Address of the Authorization Service.
+    #[arg(long, env = "FETA_AS_ADDR")]
+    pub as_addr: Option<String>,
+
+    /// URL of the IPFS API.
+    #[arg(long, env = "FETA_IPFS_URL")]
+    pub ipfs_url: Option<String>,
+
+    /// Directory shared with the Python trainer.
+    #[arg(long, env = "FETA_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Port of the ZMQ channel towards the Python trainer.
+    #[arg(long, env = "PORT")]
+    pub port: Option<u16>,
+
+    /// ID of the federated learning experiment, shared by all its clients. Taken from the VC
+    /// when not set.
+    #[arg(long, env = "FETA_SESSION_ID")]
+    pub session_id: Option<String>,
+
+    /// Total number of clients in the federation.
+    #[arg(long, env = "CLIENTS")]
+    pub clients: Option<usize>,
+
+    /// Maximum number of federated learning rounds.
+    #[arg(long, env = "ROUNDS")]
+    pub rounds: Option<u32>,
+
+    /// Stop after the round running when this many seconds have passed.
+    #[arg(long, env = "FETA_TIME_BUDGET_SECS")]
+    pub time_budget_secs: Option<u64>,
+
+    /// Seconds to wait for the models of a round.
+    #[arg(long, env = "FETA_ROUND_TIMEOUT_SECS")]
+    pub round_timeout_secs: Option<u64>,
+
+    /// Number of models needed to aggregate a round, all clients by default.
+    #[arg(long, env = "FETA_QUORUM")]
+    pub quorum: Option<usize>,
+
+    /// Largest model accepted from IPFS, in MiB.
+    #[arg(long, env = "FETA_MAX_MODEL_SIZE_MB")]
+    pub max_model_size_mb: Option<u64>,
+
+    /// Rule used to combine the models of a round.
+    #[arg(long, env = "FETA_AGGREGATOR", value_enum)]
+    pub aggregator: Option<Aggregator>,
+
+    /// Fraction of the values dropped at each end by the trimmed mean.
+    #[arg(long, env = "FETA_TRIM_RATIO")]
+    pub trim_ratio: Option<f64>,
+
+    /// Number of Byzantine clients tolerated by Krum and Multi-Krum.
+    #[arg(long, env = "FETA_BYZANTINE")]
+    pub byzantine: Option<usize>,
+
+    /// Largest distance of a model from the previous global model, larger updates are scaled down.
+    #[arg(long, env = "FETA_CLIP_NORM")]
+    pub clip_norm: Option<f64>,
+
+    /// Renew the VC when it expires within this many minutes.
+    #[arg(long, env = "FETA_RENEW_BEFORE_MINUTES")]
+    pub renew_before_minutes: Option<u64>,
+
+    /// Invite token presented to the AS when requesting a VC.
+    #[arg(long, env = "FETA_INVITE_TOKEN", hide_env_values = true)]
+    pub invite_token: Option<String>,
+
+    /// File holding the Stronghold password.
+    #[arg(long, env = "FETA_PASSWORD_FILE")]
+    pub password_file: Option<PathBuf>,
+
+    /// Publish a new identity even if one already exists.
+    #[arg(long)]
+    pub rotate_identity: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: String,
+    pub node_url: String,
+    pub as_addr: String,
+    pub ipfs_url: String,
+    pub data_dir: PathBuf,
+    pub password_file: Option<PathBuf>,
+    pub port: u16,
+    pub session_id: Option<String>,
+    pub clients: usize,
+    pub rounds: u32,
+    pub time_budget_secs: Option<u64>,
+    pub round_timeout_secs: u64,
+    pub quorum: Option<usize>,
+    pub max_model_size_mb: u64,
+    pub aggregator: Aggregator,
+    pub trim_ratio: f64,
+    pub byzantine: usize,
+    pub clip_norm: Option<f64>,
+    pub renew_before_minutes: u64,
+    /// Invite token presented to the AS when requesting a VC.
+    pub invite_token: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            network: String::from("dev"),
+            node_url: String::from("http://192.168.10.203:14265"),
+            as_addr: String::from("192.168.10.205:3333"),
+            ipfs_url: String::from("http://192.168.10.205:52004"),
+            data_dir: PathBuf::from("/mnt"),
+            password_file: None,
+            port: 5555,
+            session_id: None,
+            clients: 0,
+            rounds: 10,
+            time_budget_secs: None,
+            round_timeout_secs: 600,
+            quorum: None,
+            max_model_size_mb: 1024,
+            aggregator: Aggregator::FedAvg,
+            trim_ratio: 0.1,
+            byzantine: 0,
+            clip_norm: None,
+            renew_before_minutes: 60,
+            invite_token: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(cli: &Cli) -> Result<Config> {
+        let mut config: Config = match &cli.config {
+            Some(path) => {
+                let content = fs::read_to_string(path)?;
+                toml::from_str(&content).map_err(|err| Error::Config(err.to_string()))?
+            },
+            None => Config::default(),
+        };
+
+        if let Some(network) = &cli.network {
+            config.network = network.clone();
+        }
+        if let Some(node_url) = &cli.node_url {
+            config.node_url = node_url.clone();
+        }
+        if let Some(as_addr) = &cli.as_addr {
+            config.as_addr = as_addr.clone();
+        }
+        if let Some(ipfs_url) = &cli.ipfs_url {
+            config.ipfs_url = ipfs_url.clone();
+        }
+        if let Some(data_dir) = &cli.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+        if let Some(password_file) = &cli.password_file {
+            config.password_file = Some(password_file.clone());
+        }
+        if let Some(port) = cli.port {
+            config.port = port;
+        }
+        if let Some(session_id) = &cli.session_id {
+            config.session_id = Some(session_id.clone());
+        }
+        if let Some(clients) = cli.clients {
+            config.clients = clients;
+        }
+        if let Some(rounds) = cli.rounds {
+            config.rounds = rounds;
+        }
+        if let Some(time_budget_secs) = cli.time_budget_secs {
+            config.time_budget_secs = Some(time_budget_secs);
+        }
+        if let Some(round_timeout_secs) = cli.round_timeout_secs {
+            config.round_timeout_secs = round_timeout_secs;
+        }
+        if let Some(quorum) = cli.quorum {
+            config.quorum = Some(quorum);
+        }
+        if let Some(max_model_size_mb) = cli.max_model_size_mb {
+            config.max_model_size_mb = max_model_size_mb;
+        }
+        if let Some(aggregator) = cli.aggregator {
+            config.aggregator = aggregator;
+        }
+        if let Some(trim_ratio) = cli.trim_ratio {
+            config.trim_ratio = trim_ratio;
+        }
+        if let Some(byzantine) = cli.byzantine {
+            config.byzantine = byzantine;
+        }
+        if let Some(clip_norm) = cli.clip_norm {
+            config.clip_norm = Some(clip_norm);
+        }
+        if let Some(renew_before_minutes) = cli.renew_before_minutes {
+            config.renew_before_minutes = renew_before_minutes;
+        }
+        if let Some(invite_token) = &cli.invite_token {
+            config.invite_token = Some(invite_token.clone());
+        }
+
+        if config.clients == 0 {
+            return Err(Error::Config(String::from("the number of clients is not set (CLIENTS or --clients)")));
+        }
+        if let Some(session_id) = &config.session_id {
+            check_session_id(session_id)?;
+        }
+        //Checked here, so a typo stops the client before it joins the session
+        match config.ipfs_url.split_once("://") {
+            Some(("http" | "https", host)) if !host.is_empty() => (),
+            _ => return Err(Error::Config(format!("the IPFS URL {:?} must start with http:// or https://", config.ipfs_url))),
+        }
+        if config.rounds == 0 {
+            return Err(Error::Config(String::from("the number of rounds must be at least 1")));
+        }
+        if let Some(quorum) = config.quorum {
+            if quorum == 0 || quorum > config.clients {
+                return Err(Error::Config(format!("the quorum must be between 1 and {}", config.clients)));
+            }
+        }
+        if config.max_model_size_mb == 0 {
+            return Err(Error::Config(String::from("the maximum model size must be at least 1 MiB")));
+        }
+        if !(0.0..0.5).contains(&config.trim_ratio) {
+            return Err(Error::Config(String::from("the trim ratio must be at least 0 and below 0.5")));
+        }
+        if let Some(clip_norm) = config.clip_norm {
+            if clip_norm.is_nan() || clip_norm <= 0.0 {
+                return Err(Error::Config(String::from("the clipping norm must be positive")));
+            }
+        }
+        if matches!(config.aggregator, Aggregator::Krum | Aggregator::MultiKrum) {
+            let quorum = config.quorum.unwrap_or(config.clients);
+            if quorum < 2 * config.byzantine + 3 {
+                return Err(Error::Config(format!(
+                    "{} needs a quorum of at least {} models to tolerate {} Byzantine clients",
+                    config.aggregator, 2 * config.byzantine + 3, config.byzantine
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Password of the Stronghold, taken from `FETA_STRONGHOLD_PASSWORD`, the password file or
+    /// an interactive prompt, in this order. Empty passwords are refused for new Strongholds, and
+    /// only accepted with a warning for those created before they were.
+    pub fn stronghold_password(&self) -> Result<String> {
+        let password = if let Ok(password) = env::var("FETA_STRONGHOLD_PASSWORD") {
+            password
+        } else if let Some(path) = &self.password_file {
+            let content = fs::read_to_string(path)
+                .map_err(|err| Error::Config(format!("cannot read password file {}: {}", path.display(), err)))?;
+            content.trim_end_matches(['\n', '\r']).to_string()
+        } else if io::stdin().is_terminal() {
+            let password = rpassword::prompt_password("Insert Stronghold password: ")?;
+            if !self.path("strong.hodl").exists() {
+                let confirmation = rpassword::prompt_password("No Stronghold found, confirm the password for the new one: ")?;
+                if confirmation != password {
+                    return Err(Error::Config(String::from("the passwords do not match")));
+                }
+            }
+            password
+        } else {
+            return Err(Error::Config(String::from(
+                "no Stronghold password: set FETA_STRONGHOLD_PASSWORD, --password-file or run interactively",
+            )));
+        };
+
+        if password.is_empty() {
+            let stronghold = self.path("strong.hodl");
+            if !stronghold.exists() {
+                return Err(Error::Config(String::from("the Stronghold password must not be empty")));
+            }
+            eprintln!("WARNING: {} is protected by an empty password, whoever can read it holds the client key. Remove it with did.txt and vc.txt to create a new identity protected by a password.", stronghold.display());
+        }
+        Ok(password)
+    }
+
+    /// Location of `file` inside the data directory.
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.data_dir.join(file)
+    }
+}
+
+/// Tangle indexes hold at most 64 bytes, the session ID shares them with the
+/// `IOTAFederatedLearning#` prefix, a separator and the round number.
+pub const MAX_SESSION_ID_LEN: usize = 31;
+
+/// Session IDs are part of the Tangle index, so they are kept short and free of separators.
+pub fn check_session_id(session_id: &str) -> Result<()> {
+    if session_id.is_empty() || session_id.len() > MAX_SESSION_ID_LEN {
+        return Err(Error::Config(format!("the session ID must be 1 to {} characters long", MAX_SESSION_ID_LEN)));
+    }
+    if !session_id.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || byte == b'.') {
+        return Err(Error::Config(format!("the session ID {:?} may only contain letters, digits, '-', '_' and '.'", session_id)));
+    }
+    Ok(())
+}
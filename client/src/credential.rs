@@ -0,0 +1,91 @@
+//! Claims of the participant credentials issued by the Authorization Service.
+//!
+//! The same schema is defined in the `credential` module of the AS.
+
+use std::fmt;
+use identity_iota::core::{OneOrMany, Timestamp};
+use identity_iota::credential::Credential;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+pub const CREDENTIAL_TYPE: &str = "FetaParticipantCredential";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Trains on local data and publishes model updates.
+    Trainer,
+    /// Only aggregates the updates of the trainers.
+    Aggregator,
+    /// Only follows the federation.
+    Observer,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Trainer => write!(f, "trainer"),
+            Role::Aggregator => write!(f, "aggregator"),
+            Role::Observer => write!(f, "observer"),
+        }
+    }
+}
+
+/// Local dataset a trainer is enrolled with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dataset {
+    pub id: String,
+    /// Size of the dataset, an upper bound for the sample count declared in updates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Credential subject of a participant, besides its DID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParticipantClaims {
+    pub role: Role,
+    /// Federation session (task) the credential is valid for, any session when `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<Dataset>,
+    pub valid_from: Timestamp,
+    pub valid_until: Timestamp,
+}
+
+impl ParticipantClaims {
+    pub fn from_credential(credential: &Credential) -> Result<ParticipantClaims> {
+        if !credential.types.iter().any(|kind| kind == CREDENTIAL_TYPE) {
+            return Err(Error::Malformed(format!("not a {}", CREDENTIAL_TYPE)));
+        }
+        let subject = match &credential.credential_subject {
+            OneOrMany::One(subject) => subject,
+            OneOrMany::Many(_) => return Err(Error::Malformed(String::from("credential with several subjects"))),
+        };
+        let properties = serde_json::to_value(&subject.properties)?;
+        serde_json::from_value(properties)
+            .map_err(|err| Error::Malformed(format!("invalid participant claims: {}", err)))
+    }
+
+    /// Checks that the holder may publish updates for `session` now.
+    pub fn check_trainer(&self, session: &str) -> Result<()> {
+        if self.role != Role::Trainer {
+            return Err(Error::Verification(format!("the holder is an {} and cannot publish updates", self.role)));
+        }
+        if let Some(authorized) = &self.session {
+            if authorized != session {
+                return Err(Error::Verification(format!("credential issued for session {}", authorized)));
+            }
+        }
+        let now = Timestamp::now_utc();
+        if now < self.valid_from || now > self.valid_until {
+            return Err(Error::Verification(format!("credential valid from {} to {}", self.valid_from, self.valid_until)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Signed envelope posted on the Tangle for every model update.
+//!
+//! The envelope carries the credential of the author and describes the update (session, round,
+//! architecture, sample count, base global model, CID, hash and size of the model), so the
+//! model itself stays an unmodified blob on IPFS and receivers can discard updates
+//! that do not belong to their round before downloading them. Session and round are signed
+//! with the rest, and a random nonce makes every envelope unique, so an old update cannot be
+//! replayed under another round or session.
+
+use identity_iota::core::{FromJson, Timestamp};
+use identity_iota::credential::Credential;
+use identity_iota::crypto::{GetSignature, GetSignatureMut, Proof, SetSignature};
+use serde::{Deserialize, Serialize};
+
+use crate::credential::ParticipantClaims;
+use crate::error::{Error, Result};
+use crate::model::Model;
+use crate::PublishedModel;
+
+pub const ENVELOPE_TYPE: &str = "FetaModelUpdate";
+pub const ENVELOPE_VERSION: u32 = 3;
+
+/// Length in bytes of the random nonce of an envelope.
+const NONCE_LEN: usize = 16;
+
+/// Largest difference, in seconds, accepted between the timestamp of an update and the local clock.
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ModelUpdate {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub version: u32,
+    /// ID of the federated learning experiment the update belongs to.
+    pub session: String,
+    pub round: u32,
+    /// Random hex string, unique to this envelope.
+    pub nonce: String,
+    /// [`Model::architecture_id`] of the published model.
+    pub architecture: String,
+    pub num_samples: u64,
+    /// Hash of the global model the update was trained from, `None` in the first round.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_model_hash: Option<String>,
+    pub timestamp: Timestamp,
+    pub cid: String,
+    /// Lowercase hex SHA-256 of the IPFS object.
+    pub model_hash: String,
+    /// Size in bytes of the IPFS object.
+    pub model_size: u64,
+    pub credential: Credential,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+impl ModelUpdate {
+    /// Describes `published` for `round` of `session`. The envelope still has to be signed.
+    pub fn new(session: String, round: u32, published: &PublishedModel, base_model_hash: Option<String>, vc: &str) -> Result<ModelUpdate> {
+        let num_samples = match published.num_samples {
+            Some(num_samples) => num_samples,
+            None => return Err(Error::Malformed(String::from("the trainer did not declare its number of samples"))),
+        };
+        Ok(ModelUpdate {
+            kind: String::from(ENVELOPE_TYPE),
+            version: ENVELOPE_VERSION,
+            session,
+            round,
+            nonce: base16ct::lower::encode_string(&rand::random::<[u8; NONCE_LEN]>()),
+            architecture: published.architecture.clone(),
+            num_samples,
+            base_model_hash,
+            timestamp: Timestamp::now_utc(),
+            cid: published.cid.clone(),
+            model_hash: published.hash.clone(),
+            model_size: published.size,
+            credential: Credential::from_json(vc)?,
+            proof: None,
+        })
+    }
+
+    /// Checks the fields that do not need the model itself against what the receiver expects.
+    pub fn check(&self, context: &RoundContext) -> Result<()> {
+        if self.kind != ENVELOPE_TYPE {
+            return Err(Error::Malformed(format!("unexpected envelope type {}", self.kind)));
+        }
+        if self.version != ENVELOPE_VERSION {
+            return Err(Error::Malformed(format!("unsupported envelope version {}", self.version)));
+        }
+        if self.session != context.session {
+            return Err(Error::Verification(format!("update for session {}", self.session)));
+        }
+        //Only trainers enrolled for this session contribute updates
+        let claims = ParticipantClaims::from_credential(&self.credential)?;
+        claims.check_trainer(&context.session)?;
+        if self.round != context.round {
+            return Err(Error::Verification(format!("update for round {} found in round {}", self.round, context.round)));
+        }
+        if self.nonce.len() != 2 * NONCE_LEN || !self.nonce.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(Error::Malformed(String::from("invalid nonce")));
+        }
+        if self.model_hash.len() != 64 || !self.model_hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(Error::Malformed(String::from("invalid model hash")));
+        }
+        if let Some(architecture) = &context.architecture {
+            if &self.architecture != architecture {
+                return Err(Error::Verification(format!("update for architecture {}", self.architecture)));
+            }
+        }
+        if let Some(base_model_hash) = &context.base_model_hash {
+            if self.base_model_hash.as_ref() != Some(base_model_hash) {
+                return Err(Error::Verification(String::from("update trained on a different global model")));
+            }
+        }
+        if self.num_samples == 0 {
+            return Err(Error::Verification(String::from("update trained on zero samples")));
+        }
+        if let Some(samples) = claims.dataset.as_ref().and_then(|dataset| dataset.samples) {
+            if self.num_samples > samples {
+                return Err(Error::Verification(format!("update declares {} samples, the enrolled dataset has {}", self.num_samples, samples)));
+            }
+        }
+        if self.timestamp.to_unix() > Timestamp::now_utc().to_unix() + MAX_CLOCK_SKEW_SECS {
+            return Err(Error::Verification(format!("update timestamp {} is in the future", self.timestamp)));
+        }
+        Ok(())
+    }
+
+    /// Checks that the downloaded model is the one the envelope describes.
+    pub fn check_model(&self, model: &Model) -> Result<()> {
+        if model.architecture_id() != self.architecture {
+            return Err(Error::Verification(String::from("the model does not have the declared architecture")));
+        }
+        if model.num_samples != Some(self.num_samples) {
+            return Err(Error::Verification(String::from("the model does not have the declared number of samples")));
+        }
+        Ok(())
+    }
+}
+
+impl GetSignature for ModelUpdate {
+    fn signature(&self) -> Option<&Proof> {
+        self.proof.as_ref()
+    }
+}
+
+impl GetSignatureMut for ModelUpdate {
+    fn signature_mut(&mut self) -> Option<&mut Proof> {
+        self.proof.as_mut()
+    }
+}
+
+impl SetSignature for ModelUpdate {
+    fn set_signature(&mut self, signature: Proof) {
+        self.proof = Some(signature)
+    }
+}
+
+/// What a client expects from the updates of the round it is collecting.
+#[derive(Debug, Clone)]
+pub struct RoundContext {
+    pub session: String,
+    pub round: u32,
+    /// Architecture of the local model, any architecture is accepted when `None`.
+    pub architecture: Option<String>,
+    /// Hash of the local global model, checked only when every client aggregates the same set
+    /// of models and therefore computes the same global model.
+    pub base_model_hash: Option<String>,
+}
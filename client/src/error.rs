@@ -0,0 +1,63 @@
+//! Error type shared by every function of the client library.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    #[error("Tangle error: {0}")]
+    Tangle(#[from] iota_client::Error),
+
+    #[error("IPFS error: {0}")]
+    Ipfs(#[from] ipfs_api::Error),
+
+    #[error("account error: {0}")]
+    Account(#[from] identity_iota::account::Error),
+
+    #[error("Stronghold error: {0}")]
+    Storage(#[from] identity_iota::account_storage::Error),
+
+    #[error("identity client error: {0}")]
+    IdentityClient(#[from] identity_iota::client::Error),
+
+    #[error("IOTA DID error: {0}")]
+    IotaCore(#[from] identity_iota::iota_core::Error),
+
+    #[error("invalid DID: {0}")]
+    InvalidDid(String),
+
+    #[error("credential error: {0}")]
+    Credential(#[from] identity_iota::credential::Error),
+
+    #[error("serialization error: {0}")]
+    Core(#[from] identity_iota::core::Error),
+
+    #[error("serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("model format error: {0}")]
+    Model(#[from] safetensors::SafeTensorError),
+
+    #[error("aggregation failed: {0}")]
+    Aggregation(String),
+
+    #[error("malformed message: {0}")]
+    Malformed(String),
+
+    #[error("verification failed: {0}")]
+    Verification(String),
+}
+
+impl Error {
+    /// True for failures to reach the Tangle, IPFS or a node, which may not happen on a retry.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Io(_) | Error::Tangle(_) | Error::Ipfs(_) | Error::IdentityClient(_))
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
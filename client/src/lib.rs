@@ -1,109 +1,138 @@
+pub mod aggregation;
+pub mod config;
+pub mod credential;
+pub mod envelope;
+pub mod error;
+pub mod model;
+pub mod protocol;
+pub mod schedule;
+pub mod transfer;
+
 use std::fs::File;
-use std::{fs, io};
-use identity_iota::account::{Account, AccountBuilder, AutoSave, Error, IdentitySetup, MethodContent, Result};
-use identity_iota::client::{Client as identityClient, ClientBuilder, CredentialValidationOptions, CredentialValidator, FailFast, Resolver, ResolverBuilder};
+use identity_iota::account::{Account, AccountBuilder, AutoSave, IdentitySetup, MethodContent};
+use identity_iota::client::{Client as identityClient, ClientBuilder, CredentialValidationOptions, CredentialValidator, FailFast, Resolver, ResolverBuilder, StatusCheck};
 use identity_iota::core::{FromJson, OneOrMany, Timestamp, ToJson, Url};
 use identity_iota::credential::{Credential, Presentation, PresentationBuilder};
 use identity_iota::iota_core::{IotaDID, Network, MessageId};
 use identity_iota::account_storage::{Stronghold};
-use identity_iota::crypto::{GetSignature, GetSignatureMut, Proof, ProofOptions, SetSignature};
+use identity_iota::crypto::{Proof, ProofOptions, SetSignature};
 use ipfs_api::{IpfsApi, IpfsClient};
 use futures::stream::TryStreamExt;
+use serde::Serialize;
 use std::path::PathBuf;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 
 use identity_iota::did::verifiable::VerifierOptions;
-use iota_client::{Client, Result as clientResult};
+use iota_client::Client;
+use std::collections::HashSet;
 use std::sync::Arc;
 use iota_client::bee_message::payload::Payload;
-use sha2::{Sha256, Digest};
+use std::time::{Duration, Instant};
+
+use crate::config::{self, Config};
+use crate::credential::ParticipantClaims;
+use crate::error::{Error, Result};
+use crate::envelope::{ModelUpdate, RoundContext};
+use crate::model::{Model, ModelHeader};
+use crate::schedule::RoundOptions;
+use crate::transfer::{Download, HashingReader};
 
 
 extern crate serde;
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Signable {
-    data: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    proof: Option<Proof>,
+pub fn write_did(config: &Config, did: &IotaDID) -> Result<()> {
+    let mut output = File::create(config.path("did.txt"))?;
+    write!(output, "{}", did)?;
+    Ok(())
 }
 
-impl Signable {
-    pub fn new(data: String) -> Self {
-        Self { data, proof: None }
-    }
+pub fn write_vc(config: &Config, vc: &str) -> Result<()> {
+    let mut output = File::create(config.path("vc.txt"))?;
+    write!(output, "{}", vc)?;
+    Ok(())
 }
 
-impl GetSignature for Signable {
-    fn signature(&self) -> Option<&Proof> {
-        self.proof.as_ref()
-    }
+/// Writes the aggregated model that the trainer loads at the end of the round.
+pub fn write_global_model(config: &Config, model: &Model) -> Result<()> {
+    model.write(&config.path("global_model.safetensors"))
 }
 
-impl GetSignatureMut for Signable {
-    fn signature_mut(&mut self) -> Option<&mut Proof> {
-        self.proof.as_mut()
-    }
+/// True when a previous run left both the Stronghold and the DID it holds.
+pub fn identity_exists(config: &Config) -> bool {
+    config.path("strong.hodl").exists() && config.path("did.txt").exists()
 }
 
-impl SetSignature for Signable {
-    fn set_signature(&mut self, signature: identity_iota::crypto::Proof) {
-        self.proof = Some(signature)
+fn read_first_line(config: &Config, file: &str) -> Result<String> {
+    let reader = BufReader::new(File::open(config.path(file))?);
+    match reader.lines().next() {
+        Some(line) => Ok(line?),
+        None => Err(Error::Malformed(format!("{} is empty", file))),
     }
 }
 
-pub fn write_did(did: &IotaDID) -> std::io::Result<()> {
-    let mut output = File::create("/mnt/did.txt")?;
-    write!(output, "{}", did)
+pub fn read_did(config: &Config) -> Result<String> {
+    read_first_line(config, "did.txt")
 }
 
-pub fn write_vc(vc: &str) -> std::io::Result<()> {
-    let mut output = File::create("/mnt/vc.txt")?;
-    write!(output, "{}", vc)
+pub fn read_vc(config: &Config) -> Result<String> {
+    read_first_line(config, "vc.txt")
 }
 
-pub fn write_content(content: String) -> std::io::Result<()> {
-    let mut output = File::create("/mnt/ipfs_content.txt")?;
-    write!(output, "{}", content)
+/// Session of the federation: the configured one, otherwise the one named in the VC, otherwise
+/// `default`. A configured session must agree with the VC.
+pub fn session_id(config: &Config, vc: &str) -> Result<String> {
+    let claims = participant_claims(vc)?;
+    let session = match (&config.session_id, claims.session) {
+        (Some(configured), Some(issued)) if configured != &issued => {
+            return Err(Error::Config(format!("the VC was issued for session {}, not {}", issued, configured)));
+        },
+        (Some(configured), _) => configured.clone(),
+        (None, Some(issued)) => issued,
+        (None, None) => String::from("default"),
+    };
+    config::check_session_id(&session)?;
+    Ok(session)
 }
 
-pub fn read_did() -> std::io::Result<String> {
-    let file = File::open("/mnt/did.txt").unwrap();
-    let reader = BufReader::new(file);
-    reader.lines().enumerate().next().unwrap().1
+/// Role, session, dataset and validity window the AS granted in `vc`.
+pub fn participant_claims(vc: &str) -> Result<ParticipantClaims> {
+    ParticipantClaims::from_credential(&Credential::from_json(vc)?)
 }
 
-pub fn read_vc() -> std::io::Result<String> {
-    let file = File::open("/mnt/vc.txt").unwrap();
-    let reader = BufReader::new(file);
-    reader.lines().enumerate().next().unwrap().1
+/// True when `vc` has an expiration date less than `margin` away. VCs without one never expire.
+pub fn vc_expires_within(vc: &str, margin: Duration) -> Result<bool> {
+    let credential = Credential::from_json(vc)?;
+    Ok(match credential.expiration_date {
+        Some(expiration) => expiration.to_unix() - Timestamp::now_utc().to_unix() <= margin.as_secs() as i64,
+        None => false,
+    })
 }
 
-pub async fn create_client_iota(network_name: String, url: String) -> clientResult<Client> {
+pub async fn create_client_iota(config: &Config) -> Result<Client> {
     let client: Client = Client::builder()
-        .with_network(&network_name)
-        .with_primary_node(url.as_str(), None, None)?
+        .with_network(&config.network)
+        .with_primary_node(config.node_url.as_str(), None, None)?
         .finish()
         .await?;
     Ok(client)
 }
 
-pub async fn create_client_identity(network_name: String, url: String) -> Result<identityClient> {
-    let network = Network::try_from_name(network_name)?;
+pub async fn create_client_identity(config: &Config) -> Result<identityClient> {
+    let network = Network::try_from_name(config.network.clone())?;
 
     let client: identityClient = ClientBuilder::new()
         .network(network.clone())
-        .primary_node(url.as_str(), None, None)?
+        .primary_node(config.node_url.as_str(), None, None)?
         .build()
         .await?;
     Ok(client)
 }
 
-pub async fn create_builder(password: String, network_name: String, url: String) -> Result<AccountBuilder> {
-    let stronghold_path: PathBuf = "/mnt/strong.hodl".into();
+pub async fn create_builder(password: String, config: &Config) -> Result<AccountBuilder> {
+    let stronghold_path: PathBuf = config.path("strong.hodl");
     let stronghold: Stronghold = Stronghold::new(&stronghold_path, password, None).await?;
 
-    let network = Network::try_from_name(network_name)?;
+    let network = Network::try_from_name(config.network.clone())?;
 
     let builder: AccountBuilder = Account::builder()
         .autosave(AutoSave::Every)
@@ -112,38 +141,29 @@ pub async fn create_builder(password: String, network_name: String, url: String)
         .client_builder(
             ClientBuilder::new()
                 .network(network.clone())
-                .primary_node(url.as_str(), None, None)?,
+                .primary_node(config.node_url.as_str(), None, None)?,
         );
     Ok(builder)
 }
 
 pub async fn create_identity(builder: &mut AccountBuilder) -> Result<Account> {
-    match builder.create_identity(IdentitySetup::default()).await {
-        Ok(mut identity) => {
-            identity
-                .update_identity()
-                .create_method()
-                .content(MethodContent::GenerateEd25519)
-                .fragment("SCKey")
-                .apply()
-                .await?;
-            Ok(identity)
-        },
-        Err(err) => {
-            Err(err)
-        }
-    }
+    let mut identity = builder.create_identity(IdentitySetup::default()).await?;
+    identity
+        .update_identity()
+        .create_method()
+        .content(MethodContent::GenerateEd25519)
+        .fragment("SCKey")
+        .apply()
+        .await?;
+    Ok(identity)
 }
 
 pub async fn load_identity(builder: &mut AccountBuilder, did: IotaDID) -> Result<Account> {
-    match builder.load_identity(did).await {
-        Ok(issuer) => Ok(issuer),
-        Err(err) => Err(err),
-    }
+    Ok(builder.load_identity(did).await?)
 }
 
-pub async fn create_vp(credential_json: &String, holder: &Account, challenge: (String, Timestamp)) -> Result<String> {
-    let credential: Credential = Credential::from_json(credential_json.as_str())?;
+pub async fn create_vp(credential_json: &str, holder: &Account, challenge: (String, Timestamp)) -> Result<String> {
+    let credential: Credential = Credential::from_json(credential_json)?;
 
     let mut presentation: Presentation = PresentationBuilder::default()
         .holder(Url::parse(holder.did().as_ref())?)
@@ -163,131 +183,296 @@ pub async fn create_vp(credential_json: &String, holder: &Account, challenge: (S
     Ok(presentation_json)
 }
 
-pub async fn create_ipfs_content(user: &Account) -> Result<()> {
-    let mut file = File::open("/mnt/simple.json").unwrap();
-    let mut model = fs::read_to_string("/mnt/simple.json").unwrap();
-
-    let mut hasher = Sha256::new();
-    io::copy(&mut model.as_bytes(), &mut hasher).unwrap();
-    let hash = hasher.finalize();
-    let mut hex_hash = base16ct::lower::encode_string(&hash);
-    let mut signed_hash = Signable::new(hex_hash.clone());
-    user.sign("SCKey", &mut signed_hash, Default::default()).await?;
-    let signable_serialized = serde_json::to_string(&signed_hash).unwrap();
-    model.push('\n');
-    model.push_str(&signable_serialized);
-
-    write_content(model);
-    Ok(())
+pub const DID_CONTROL_TYPE: &str = "FetaDidControlProof";
+
+/// Document signed with `#SCKey` over a challenge of the AS, to prove the client controls its
+/// DID before a VC is issued to it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DidControlProof {
+    #[serde(rename = "type")]
+    kind: String,
+    did: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proof: Option<Proof>,
 }
 
-pub async fn upload_to_tangle(user: &Account, cid: String, mut vc: String, index: &String) -> Result<()> {
-    let client = create_client_iota(String::from("dev"), String::from("http://192.168.10.203:14265")).await.unwrap();
+impl SetSignature for DidControlProof {
+    fn set_signature(&mut self, signature: Proof) {
+        self.proof = Some(signature)
+    }
+}
 
-    vc.push('\n');
-    vc.push_str(&cid);
+/// Signs `challenge` with the `#SCKey` method of `holder`, returning the proof as JSON.
+pub async fn prove_did_control(holder: &Account, challenge: (String, Timestamp)) -> Result<String> {
+    let mut control = DidControlProof {
+        kind: String::from(DID_CONTROL_TYPE),
+        did: holder.did().to_string(),
+        proof: None,
+    };
 
-    let mut vccid = Signable::new(vc.clone());
-    user.sign("SCKey", &mut vccid, Default::default()).await?;
+    holder
+        .sign(
+            "#SCKey",
+            &mut control,
+            ProofOptions::new().challenge(challenge.0).expires(challenge.1),
+        )
+        .await?;
 
-    let mut tag = String::from("IOTAFederatedLearning#");
-    tag.push_str(&index);
-    let content = serde_json::to_vec(&vccid).unwrap();
+    Ok(control.to_json()?)
+}
 
-    let message = client
-        .message()
-        .with_index(tag)
-        .with_data(content)
-        .finish()
-        .await;
+/// Model uploaded to IPFS, which the envelope of the update describes.
+pub struct PublishedModel {
+    /// [`Model::architecture_id`] of the uploaded model.
+    pub architecture: String,
+    pub num_samples: Option<u64>,
+    pub cid: String,
+    /// Lowercase hex SHA-256 of the uploaded file.
+    pub hash: String,
+    pub size: u64,
+}
 
-    Ok(())
+/// Streams the model written by the trainer to IPFS, unmodified, hashing it on the way.
+/// Only the header is parsed, the tensors are never held in memory.
+pub async fn upload_model(config: &Config, client: &IpfsClient) -> Result<PublishedModel> {
+    let (mut reader, tracker) = HashingReader::new(File::open(config.path("model.safetensors"))?);
+    //Refuse to publish something the other clients could not load
+    let (header, header_bytes) = ModelHeader::read(&mut reader)?;
+    let added = client.add(io::Cursor::new(header_bytes).chain(reader)).await?;
+
+    //The file is read once, so the hash is the one of the uploaded content
+    let (hash, size) = tracker.finish();
+    if size != header.file_size {
+        return Err(Error::Verification(format!("model.safetensors holds {} bytes, its header describes {}", size, header.file_size)));
+    }
+    Ok(PublishedModel { architecture: header.architecture, num_samples: header.num_samples, cid: added.hash, hash, size })
 }
 
-pub async fn get_models(client: &IpfsClient, index: &String, issuer_did: &IotaDID, clients_number: &usize) -> Result<Vec<String>> {
-    let mut res = Vec::new();
-    let iota_client = create_client_iota(String::from("dev"), String::from("http://192.168.10.203:14265")).await.unwrap();
+/// Attempts made to post a model update before giving up.
+const UPLOAD_ATTEMPTS: u32 = 5;
+/// Delay before the first retry, doubled after every failed attempt.
+const UPLOAD_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Signs `update` and posts it under the index of its round.
+pub async fn upload_to_tangle(config: &Config, user: &Account, mut update: ModelUpdate) -> Result<MessageId> {
+    let client = create_client_iota(config).await?;
+
+    user.sign("SCKey", &mut update, Default::default()).await?;
+
+    let tag = round_tag(&update.session, update.round);
+    let content = serde_json::to_vec(&update)?;
+
+    let mut attempt = 1;
+    let mut backoff = UPLOAD_BACKOFF;
+    loop {
+        let message = client
+            .message()
+            .with_index(&tag)
+            .with_data(content.clone())
+            .finish()
+            .await;
+
+        match message {
+            Ok(message) => return Ok(message.id().0),
+            Err(_) if attempt < UPLOAD_ATTEMPTS => {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            },
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Index of the updates of `round`, namespaced by session so that federations sharing a Tangle
+/// do not see each other.
+fn round_tag(session: &str, round: u32) -> String {
+    format!("IOTAFederatedLearning#{}#{}", session, round)
+}
+
+/// Model update read from a Tangle message, before any verification.
+struct Update {
+    /// Index the message was posted under.
+    tag: String,
+    envelope: ModelUpdate,
+    author: IotaDID,
+}
+
+fn parse_update(payload: &Option<Payload>) -> Result<Update> {
+    let box_m = match payload {
+        Some(Payload::Indexation(box_m)) => box_m,
+        _ => return Err(Error::Malformed(String::from("not an indexation payload"))),
+    };
+    let envelope: ModelUpdate = serde_json::from_slice(box_m.as_ref().data())?;
 
-    let identity_client: identityClient = match create_client_identity(String::from("dev"), String::from("http://192.168.10.203:14265")).await {
-        Ok(client) => client,
-        Err(err) => return Err(err),
+    let sub = match &envelope.credential.credential_subject {
+        OneOrMany::One(sub) => sub,
+        OneOrMany::Many(_vec) => return Err(Error::Malformed(String::from("credential with several subjects"))),
     };
+    let author: IotaDID = match &sub.id {
+        Some(id) => IotaDID::parse(id.as_str()).map_err(|err| Error::InvalidDid(err.to_string()))?,
+        None => return Err(Error::Malformed(String::from("credential subject without id"))),
+    };
+
+    let tag = String::from_utf8_lossy(box_m.index()).into_owned();
+
+    Ok(Update { tag, envelope, author })
+}
+
+/// Checks the signature, the credential and the model hash of `update`, returning the model.
+async fn verify_update(update: &Update, client: &IpfsClient, resolver: &Resolver, issuer_did: &IotaDID, max_size: u64) -> Result<Model> {
+    //Verify the signature on the data uploaded to the tangle
+    let doc = resolver.resolve(&update.author).await?.document;
+    doc.verify_data(&update.envelope, &VerifierOptions::default())
+        .map_err(|err| Error::Verification(format!("invalid signature from {}: {}", update.author, err)))?;
+
+    let issuer_doc = resolver.resolve(issuer_did).await?.document;
+    //Verify the VC contained in the data uploaded to the tangle, credentials revoked by the
+    //issuer drop their holder from the aggregation
+    CredentialValidator::validate(
+        &update.envelope.credential,
+        &issuer_doc,
+        &CredentialValidationOptions::default().status(StatusCheck::Strict),
+        FailFast::FirstError,
+    ).map_err(|err| Error::Verification(format!("invalid credential of {}: {}", update.author, err)))?;
+
+    if update.envelope.model_size > max_size {
+        return Err(Error::Verification(format!("declared model size {} exceeds the limit of {} bytes", update.envelope.model_size, max_size)));
+    }
+    //The model is hashed while it is downloaded, and the download stops past the declared size
+    let mut download = Download::new(update.envelope.model_size);
+    let mut stream = client.cat(&update.envelope.cid);
+    while let Some(chunk) = stream.try_next().await? {
+        download.push(&chunk)?;
+    }
+    let (bytes, hash) = download.finish();
+
+    //Verify the hash signed in the envelope
+    if bytes.len() as u64 != update.envelope.model_size || hash != update.envelope.model_hash {
+        return Err(Error::Verification(format!("model hash mismatch from {}", update.author)));
+    }
+    let model = Model::from_bytes(&bytes)?;
+    update.envelope.check_model(&model)?;
+    Ok(model)
+}
+
+/// Models collected for one round.
+pub struct RoundModels {
+    /// Verified models, sorted by author so that clients holding the same set aggregate it
+    /// in the same order.
+    pub models: Vec<Model>,
+    /// DIDs of the clients whose model was accepted, in the same order as `models`.
+    pub participants: Vec<IotaDID>,
+    /// Messages found under the round index that did not pass verification, with the reason.
+    pub rejected: Vec<(MessageId, String)>,
+    /// False when the deadline passed before the quorum was reached.
+    pub quorum_reached: bool,
+}
+
+pub async fn get_models(config: &Config, client: &IpfsClient, context: &RoundContext, issuer_did: &IotaDID, options: &RoundOptions) -> Result<RoundModels> {
+    let mut res = Vec::new();
+    let mut participants: Vec<IotaDID> = Vec::new();
+    let mut rejected: Vec<(MessageId, String)> = Vec::new();
+    let iota_client = create_client_iota(config).await?;
+
+    let identity_client: identityClient = create_client_identity(config).await?;
 
     let resolver_builder: ResolverBuilder = ResolverBuilder::new();
-    let resolver: Resolver = resolver_builder.client(Arc::from(identity_client)).build().await.unwrap();
+    let resolver: Resolver = resolver_builder.client(Arc::from(identity_client)).build().await?;
+
+    let tag = round_tag(&context.session, context.round);
+    //Every message is accepted or rejected once, but only verified models from distinct DIDs count toward the quorum
+    let mut message_ids_seen: HashSet<MessageId> = HashSet::new();
+    //The same signed envelope re-posted in another message is a replay. Only nonces of verified
+    //updates are recorded, so a forged copy seen first cannot shadow the genuine update
+    let mut nonces_seen: HashSet<String> = HashSet::new();
+    let started = Instant::now();
+    let mut poll_interval = options.poll_interval;
+    while participants.len() < options.quorum {
+        if started.elapsed() >= options.deadline {
+            break;
+        }
+        let accepted_before = participants.len();
 
-    let mut tag = String::from("IOTAFederatedLearning#");
-    tag.push_str(&index);
-    let mut message_ids_received: Vec<MessageId> = Vec::new();
-    while message_ids_received.len() < *clients_number {
-        let fetched_message_ids = iota_client.get_message().index(&tag).await.unwrap();
+        let fetched_message_ids = iota_client.get_message().index(&tag).await?;
         for message_id in fetched_message_ids.iter() {
-            if message_ids_received.contains(&message_id) {
+            if started.elapsed() >= options.deadline {
+                break;
+            }
+            if message_ids_seen.contains(message_id) {
+                continue;
+            }
+            //A message that cannot be fetched now is retried at the next poll
+            let message = match iota_client.get_message().data(message_id).await {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            message_ids_seen.insert(message_id.clone());
+
+            let update = match parse_update(message.payload()) {
+                Ok(update) => update,
+                Err(err) => {
+                    rejected.push((message_id.clone(), err.to_string()));
+                    continue;
+                },
+            };
+            if update.tag != tag {
+                rejected.push((message_id.clone(), format!("posted under index {}", update.tag)));
+                continue;
+            }
+            if let Err(err) = update.envelope.check(context) {
+                rejected.push((message_id.clone(), err.to_string()));
                 continue;
-            } else {
-                message_ids_received.push(message_id.clone());
             }
-            let payload = iota_client.get_message().data(&message_id).await.unwrap().payload().to_owned().unwrap();
-
-            if let Payload::Indexation(box_m) = payload {
-                let data: Signable = serde_json::from_slice(box_m.as_ref().data()).unwrap();
-                
-                let mut lines = data.data.lines();
-                
-                let credential: Credential = Credential::from_json(lines.next().unwrap()).unwrap();
-                let cid = lines.next().unwrap();
-
-                let sub = match credential.clone().credential_subject {
-                    OneOrMany::One(sub) => sub,
-                    OneOrMany::Many(_vec) => return Err(Error::IdentityNotFound),
-                };
-                let user_did: IotaDID = IotaDID::parse(sub.id.unwrap().to_string()).unwrap();
-
-                //Verify the signature on the data uploaded to the tangle
-                let doc = resolver.resolve(&user_did).await.unwrap().document;
-                let ver: bool = doc
-                    .verify_data(&data, &VerifierOptions::default())
-                    .is_ok();
-                if ver {
-                    let issuer_doc = resolver.resolve(&issuer_did).await.unwrap().document;
-                    //Verify the VC contained in the data uploaded to the tangle
-                    CredentialValidator::validate(
-                        &credential,
-                        &issuer_doc,
-                        &CredentialValidationOptions::default(),
-                        FailFast::FirstError,
-                    ).unwrap();
-                    let download = client
-                        .cat(&cid)
-                        .map_ok(|chunk| chunk.to_vec())
-                        .try_concat()
-                        .await;
-
-                    let ipfs_content: String = String::from_utf8(download.unwrap()).unwrap();
-                    let mut lines = ipfs_content.lines();
-                    let mut model = lines.next().unwrap().to_string();
-
-                    let sign = lines.next().unwrap().trim_end_matches(['\0', ' ']).to_string();
-                    let signed_hash: Signable = serde_json::from_str(&sign).unwrap();
-                    //Verify the signature on the hash
-                    let ver: bool = doc
-                        .verify_data(&signed_hash, &VerifierOptions::default())
-                        .is_ok();
-                    if ver {
-                        //Verify the hash
-                        let mut hasher = Sha256::new();
-                        io::copy(&mut model.as_bytes(), &mut hasher).unwrap();
-                        let hash = hasher.finalize();
-
-                        let mut hex_hash = base16ct::lower::encode_string(&hash);
-   
-                        if hex_hash.eq(&signed_hash.data) {
-                            res.push(model.to_string());
-                        }
-                    }
-                }
+            if nonces_seen.contains(&update.envelope.nonce) {
+                rejected.push((message_id.clone(), format!("replayed update from {}", update.author)));
+                continue;
             }
+            if participants.contains(&update.author) {
+                rejected.push((message_id.clone(), format!("{} already contributed to this round", update.author)));
+                continue;
+            }
+
+            //A CID nobody serves would block the download, so verification ends with the round
+            let remaining = options.deadline.saturating_sub(started.elapsed());
+            match tokio::time::timeout(remaining, verify_update(&update, client, &resolver, issuer_did, options.max_model_size)).await {
+                Ok(Ok(model)) => {
+                    nonces_seen.insert(update.envelope.nonce.clone());
+                    res.push(model);
+                    participants.push(update.author);
+                },
+                //A transport failure says nothing about the update, it is verified again at the next poll
+                Ok(Err(err)) if err.is_transient() => {
+                    message_ids_seen.remove(message_id);
+                },
+                Ok(Err(err)) => rejected.push((message_id.clone(), err.to_string())),
+                Err(_) => rejected.push((message_id.clone(), format!("verification of the update from {} timed out", update.author))),
+            }
+        }
+
+        if participants.len() >= options.quorum {
+            break;
         }
+        //Poll quickly while updates keep arriving, back off while the index is quiet
+        poll_interval = if participants.len() > accepted_before {
+            options.poll_interval
+        } else {
+            (poll_interval * 2).min(options.max_poll_interval)
+        };
+        let remaining = options.deadline.saturating_sub(started.elapsed());
+        tokio::time::sleep(poll_interval.min(remaining)).await;
     }
-    Ok(res)
+
+    let quorum_reached = participants.len() >= options.quorum;
+    let mut accepted: Vec<(IotaDID, Model)> = participants.into_iter().zip(res).collect();
+    accepted.sort_by_key(|(did, _)| did.to_string());
+    let (participants, models) = accepted.into_iter().unzip();
+
+    Ok(RoundModels {
+        quorum_reached,
+        models,
+        participants,
+        rejected,
+    })
 }
\ No newline at end of file
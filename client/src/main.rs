@@ -1,20 +1,272 @@
-use std::{io, fs};
+use std::io;
 use std::net::{TcpStream};
-use std::io::{Write, Read, Cursor};
-use std::str::from_utf8;
-use bstr::B;
+use std::io::Write;
 use identity_iota::account::{Account, AccountBuilder};
 use identity_iota::core::Timestamp;
 use identity_iota::iota_core::IotaDID;
 use ipfs_api::{IpfsApi, IpfsClient, TryFromUri};
-use std::fs::{File, OpenOptions};
-use std::time::Instant;
-use std::env;
-mod lib;
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::time::{Duration, Instant};
+use clap::Parser;
+
+use client as lib;
+use lib::aggregation::{self, AggregationOptions};
+use lib::config::{Cli, Config};
+use lib::credential::Role;
+use lib::envelope::{ModelUpdate, RoundContext};
+use lib::protocol::{self, ErrorCode, Frame};
+use lib::schedule::{RoundOptions, RoundPolicy, TrainerReport};
+
+/// How many times the VP exchange is restarted when the AS reports an expired challenge.
+const MAX_VP_ATTEMPTS: u32 = 3;
+
+/// Asks the AS for a VC, with the configured invite token, and signs the challenge the AS
+/// answers with to prove control of `did`. Returns `None` after printing the reason when no
+/// VC is issued.
+async fn request_vc(stream: &mut TcpStream, config: &Config, did: &str, user: &Account) -> Option<String> {
+    let invite_token = config.invite_token.clone().unwrap_or_default();
+    if let Err(err) = protocol::write_frame(stream, &Frame::Command(String::from("vc")))
+        .and_then(|_| protocol::write_frame(stream, &Frame::Did(did.to_string())))
+        .and_then(|_| protocol::write_frame(stream, &Frame::Invite(invite_token))) {
+        eprintln!("Error: {:?}", err);
+        return None
+    }
+
+    let challenge: String = match protocol::read_frame(stream) {
+        Ok(Frame::Challenge(challenge)) => challenge,
+        Ok(Frame::Error { code, message }) => {
+            eprintln!("The AS refused to issue a VC ({}): {}", code, message);
+            return None
+        },
+        Ok(other) => {
+            eprintln!("Error: expected challenge, received {:?}", other);
+            return None
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return None
+        },
+    };
+    let expires: Timestamp = match protocol::read_frame(stream) {
+        Ok(Frame::Expiry(timestr)) => match Timestamp::parse(&timestr) {
+            Ok(t) => t,
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return None
+            },
+        },
+        Ok(other) => {
+            eprintln!("Error: expected expiry, received {:?}", other);
+            return None
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return None
+        },
+    };
+
+    let proof: String = match lib::prove_did_control(user, (challenge, expires)).await {
+        Ok(proof) => proof,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return None
+        },
+    };
+    if let Err(err) = protocol::write_frame(stream, &Frame::Proof(proof)) {
+        eprintln!("Error: {:?}", err);
+        return None
+    }
+
+    match protocol::read_frame(stream) {
+        Ok(Frame::Vc(vc)) => Some(vc),
+        Ok(Frame::Error { code, message }) => {
+            eprintln!("The AS refused to issue a VC ({}): {}", code, message);
+            None
+        },
+        Ok(other) => {
+            eprintln!("Error: expected VC, received {:?}", other);
+            None
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            None
+        },
+    }
+}
+
+/// Runs the challenge/response exchange opened by `command` ("vp" or "renew"): the AS sends a
+/// challenge and the client answers with a VP of `vc`. Expired challenges are retried.
+/// Returns the final answer of the AS, or `None` after printing the error.
+async fn present_vc(stream: &mut TcpStream, command: &str, did: &str, vc: &str, user: &Account) -> Option<Frame> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        if let Err(err) = protocol::write_frame(stream, &Frame::Command(command.to_string()))
+            .and_then(|_| protocol::write_frame(stream, &Frame::Did(did.to_string()))) {
+            eprintln!("Error: {:?}", err);
+            return None
+        }
+
+        let challenge: String = match protocol::read_frame(stream) {
+            Ok(Frame::Challenge(challenge)) => challenge,
+            Ok(Frame::Error { code, message }) => return Some(Frame::Error { code, message }),
+            Ok(other) => {
+                eprintln!("Error: expected challenge, received {:?}", other);
+                return None
+            },
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return None
+            },
+        };
+
+        let timestr: String = match protocol::read_frame(stream) {
+            Ok(Frame::Expiry(timestr)) => timestr,
+            Ok(other) => {
+                eprintln!("Error: expected expiry, received {:?}", other);
+                return None
+            },
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return None
+            },
+        };
+        let timestamp: Timestamp = match Timestamp::parse(&timestr) {
+            Ok(t) => t,
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return None
+            },
+        };
+
+        let vp: String = match lib::create_vp(vc, user, (challenge, timestamp)).await {
+            Ok(vp) => {
+                println!("VP created!");
+                vp
+            },
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return None
+            },
+        };
+
+        if let Err(err) = protocol::write_frame(stream, &Frame::Vp(vp)) {
+            eprintln!("Error: {:?}", err);
+            return None
+        }
+
+        match protocol::read_frame(stream) {
+            Ok(Frame::Error { code: ErrorCode::ChallengeExpired, message }) if attempts < MAX_VP_ATTEMPTS => {
+                eprintln!("Challenge expired ({}), requesting a new one", message);
+            },
+            Ok(frame) => return Some(frame),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return None
+            },
+        }
+    }
+}
+
+/// Ends the session with the AS. Failures are ignored, the connection is dropped anyway.
+fn close_as_session(stream: &mut TcpStream) {
+    let _ = protocol::write_frame(stream, &Frame::Command(String::from("shutdown")));
+}
+
+/// Tells the trainer to stop, so client.py does not wait for a reply that never comes.
+fn stop_trainer(socket: &zmq::Socket) {
+    if let Err(err) = socket.send("stop", 0) {
+        eprintln!("Error: {:?}", err);
+    }
+}
+
+/// Replaces vc.txt with a new VC when the current one expires within the configured margin.
+/// The AS renews a VC that is still valid, otherwise a new one is requested from scratch.
+/// Each renewal opens its own connection to the AS, since one kept open during the federation
+/// may have been dropped. Returns false when the VC expired and no new one could be obtained.
+async fn renew_vc_if_needed(config: &Config, did: &str, user: &Account) -> bool {
+    let vc: String = match lib::read_vc(config) {
+        Ok(vc) => vc,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return false
+        },
+    };
+    match lib::vc_expires_within(&vc, Duration::from_secs(config.renew_before_minutes * 60)) {
+        Ok(false) => return true,
+        Ok(true) => println!("The VC expires soon, renewing it"),
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return false
+        },
+    }
+
+    let renewed: Option<String> = match TcpStream::connect(&config.as_addr) {
+        Ok(mut stream) => {
+            let renewed = renew_vc(&mut stream, config, did, &vc, user).await;
+            close_as_session(&mut stream);
+            renewed
+        },
+        Err(err) => {
+            eprintln!("Error: cannot reach the AS at {}: {}", config.as_addr, err);
+            None
+        },
+    };
+    let renewed: String = match renewed {
+        Some(renewed) => renewed,
+        //The current VC is kept until it actually expires, the renewal is retried next round
+        None => return match lib::vc_expires_within(&vc, Duration::ZERO) {
+            Ok(false) => {
+                println!("Keeping the current VC, the renewal is retried in the next round");
+                true
+            },
+            _ => false,
+        },
+    };
+
+    match lib::write_vc(config, &renewed) {
+        Ok(..) => {
+            println!("VC renewed and saved in vc.txt");
+            true
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            false
+        },
+    }
+}
+
+/// Renews `vc` over `stream`, falling back to a new VC request when the AS refuses the renewal.
+async fn renew_vc(stream: &mut TcpStream, config: &Config, did: &str, vc: &str, user: &Account) -> Option<String> {
+    let renewed: Option<String> = match present_vc(stream, "renew", did, vc, user).await {
+        Some(Frame::Vc(vc)) => Some(vc),
+        Some(Frame::Error { code, message }) => {
+            eprintln!("The AS refused to renew the VC ({}): {}", code, message);
+            None
+        },
+        Some(other) => {
+            eprintln!("Error: expected VC, received {:?}", other);
+            None
+        },
+        None => None,
+    };
+    match renewed {
+        Some(vc) => Some(vc),
+        None => request_vc(stream, config, did, user).await,
+    }
+}
 
 #[tokio::main]
 async fn main() {
-
+    let cli = Cli::parse();
+    let config: Config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error: cannot load configuration: {}", err);
+            return
+        },
+    };
     
     let stdin = io::stdin();
     let mut user: Option<Account> = None;
@@ -23,15 +275,26 @@ async fn main() {
     let mut latency: Vec<u128> = Vec::new();
 
 
-    //println!("Insert Stronghold password:");
-    //println!("If the stronghold does not exists a new one will be created with the password of your choice");
-    let password = String::from("");
+    let password: String = match config.stronghold_password() {
+        Ok(password) => password,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return
+        },
+    };
+
+    let rotate = cli.rotate_identity;
+    let existing = lib::identity_exists(&config);
 
-    let mut builder: AccountBuilder = match lib::create_builder(password, String::from("dev"), String::from("http://192.168.10.203:14265")).await {
+    let mut builder: AccountBuilder = match lib::create_builder(password, &config).await {
         Ok(res) => {
             println!("\nBuilder created!");
             res
         },
+        Err(err) if config.path("strong.hodl").exists() => {
+            eprintln!("Error: cannot open the Stronghold at {}, the password is wrong or the snapshot is damaged: {:?}", config.path("strong.hodl").display(), err);
+            return
+        },
         Err(err) => {
             eprintln!("Error: {:?}", err);
             return
@@ -41,27 +304,29 @@ async fn main() {
     //println!("\nWhat do you want to do? (Insert the right number)\n1) Create my identity on the IOTA tangle\n2) I already have an identity\n");
 
     let now = Instant::now();
-    user = Some(match lib::create_identity(&mut builder).await {
-        Ok(identity) => {
-            println!("Identity created! DID: {}", identity.did());
-            latency.push(now.elapsed().as_nanos());
-
-            identity
-        },
-        Err(err) => {
-            eprintln!("Error: {:?}", err);
-            return
-        },
-    });
-    match lib::write_did(user.as_ref().unwrap().did()) {
-        Ok(..) => println!("Did saved in did.txt"),
-        Err(err) => {
-            eprintln!("Error: {:?}", err);
-            return
-        },
+    if rotate || !existing {
+        user = Some(match lib::create_identity(&mut builder).await {
+            Ok(identity) => {
+                println!("Identity created! DID: {}", identity.did());
+                latency.push(now.elapsed().as_nanos());
+
+                identity
+            },
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return
+            },
+        });
+        match lib::write_did(&config, user.as_ref().unwrap().did()) {
+            Ok(..) => println!("Did saved in did.txt"),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return
+            },
+        }
     }
     
-    let did: String = match lib::read_did() {
+    let did: String = match lib::read_did(&config) {
         Ok(did) => did,
         Err(err) => {
             eprintln!("Error: {:?}", err);
@@ -76,38 +341,34 @@ async fn main() {
         },
     };
 
+    if user.is_none() {
+        user = Some(match lib::load_identity(&mut builder, user_did.clone()).await {
+            Ok(identity) => {
+                println!("Identity loaded! DID: {}", identity.did());
+                latency.push(now.elapsed().as_nanos());
+
+                identity
+            },
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return
+            },
+        });
+    }
+
             
 
-    match TcpStream::connect("192.168.10.205:3333") {
+    match TcpStream::connect(&config.as_addr) {
         Ok(mut stream) => {
-            println!("\nSuccessfully connected to server in port 3333");
-            let mut data = [0 as u8; 564]; //564 byte buffer
-            
+            println!("\nSuccessfully connected to server at {}", config.as_addr);
             let now = Instant::now();
 
-            stream.write(b"vc").unwrap();
-
-            match stream.read(&mut data) {
-                Ok(..) => println!(),
-                Err(err) => {
-                    eprintln!("Error: {:?}", err);
-                    return
-                },
-            };
-
-            stream.write(B(did.as_str())).unwrap();
-
-            let vc: &str = match stream.read(&mut data) {
-                Ok(size) => {
-                    from_utf8(&data[0..size]).unwrap()
-                }
-                Err(err) => {
-                    eprintln!("Error: {:?}", err);
-                    return
-                },
+            let vc: String = match request_vc(&mut stream, &config, &did, user.as_ref().unwrap()).await {
+                Some(vc) => vc,
+                None => return,
             };
 
-            match lib::write_vc(vc) {
+            match lib::write_vc(&config, &vc) {
                 Ok(..) => println!("VC created and saved in vc.txt"),
                 Err(err) => {
                     eprintln!("Error: {:?}", err);
@@ -118,69 +379,58 @@ async fn main() {
             latency.push(now.elapsed().as_nanos());
         
             let now = Instant::now();
-            stream.write(b"vp").unwrap();
-
-            let challenge: String = match stream.read(&mut data) {
-                Ok(size) => {
-                    from_utf8(&data[0..size]).unwrap().to_string()
-                }
+            let vc: String = match lib::read_vc(&config) {
+                Ok(vc) => vc,
                 Err(err) => {
                     eprintln!("Error: {:?}", err);
                     return
                 },
             };
 
-            stream.write(b"ack").unwrap();
-
-            let timestr: &str = match stream.read(&mut data) {
-                Ok(size) => from_utf8(&data[0..size]).unwrap(),
-                Err(err) => {
-                    eprintln!("Error: {:?}", err);
+            issuer_did = Some(match present_vc(&mut stream, "vp", &did, &vc, user.as_ref().unwrap()).await {
+                Some(Frame::Result(did)) => {
+                    match IotaDID::parse(did) {
+                        Ok(did) => did,
+                        Err(err) => {
+                            eprintln!("Error: {:?}", err);
+                            return
+                        },
+                    }
+                },
+                Some(Frame::Error { code, message }) => {
+                    eprintln!("The AS rejected the VP ({}): {}", code, message);
                     return
                 },
-            };
-            let timestamp: Timestamp = match Timestamp::parse(timestr) {
-                Ok(t) => t,
-                Err(err) => {
-                    eprintln!("Error: {:?}", err);
+                Some(other) => {
+                    eprintln!("Error: expected issuer DID, received {:?}", other);
                     return
                 },
-            };
+                None => return,
+            });
+            latency.push(now.elapsed().as_nanos());
 
-            let vc: String = match lib::read_vc() {
-                Ok(vc) => vc,
+            let session = match lib::session_id(&config, &vc) {
+                Ok(session) => session,
                 Err(err) => {
                     eprintln!("Error: {:?}", err);
                     return
                 },
             };
-
-            let vp: String = match lib::create_vp(&vc, user.as_mut().unwrap(), (challenge, timestamp)).await {
-                Ok(vp) => {
-                    println!("VP created!");
-                    vp
+            println!("Joining session {}", session);
+            //Renewals open their own connection, an idle one would hold a session of the AS
+            close_as_session(&mut stream);
+
+            //Updates from any other role are discarded by every peer
+            match lib::participant_claims(&vc) {
+                Ok(claims) if claims.role == Role::Trainer => {
+                    if let Some(dataset) = &claims.dataset {
+                        println!("Enrolled as trainer with dataset {}", dataset.id);
+                    }
                 },
-                Err(err) => {
-                    eprintln!("Error: {:?}", err);
+                Ok(claims) => {
+                    eprintln!("The VC grants the {} role, only trainers take part in the training rounds", claims.role);
                     return
                 },
-            };
-            latency.push(now.elapsed().as_nanos());
-
-
-            stream.write(B(vp.as_str())).unwrap();
-
-            match stream.read(&mut data) {
-                Ok(size) => {
-                    let did = from_utf8(&data[0..size]).unwrap();
-                    issuer_did = Some(match IotaDID::parse(did) {
-                        Ok(did) => did,
-                        Err(err) => {
-                            eprintln!("Error: {:?}", err);
-                            return
-                        },
-                    });
-                },
                 Err(err) => {
                     eprintln!("Error: {:?}", err);
                     return
@@ -188,102 +438,167 @@ async fn main() {
             }
 
             
-            let client = IpfsClient::from_str("http://192.168.10.205:52004").unwrap();
+            let client = match IpfsClient::from_str(&config.ipfs_url) {
+                Ok(client) => client,
+                Err(err) => {
+                    eprintln!("Error: invalid IPFS URL {}: {}", config.ipfs_url, err);
+                    return
+                },
+            };
   
                         
-            let mut round = 0;
+            let mut round: u32 = 0;
+            let policy = RoundPolicy::from_config(&config);
+            let round_options = RoundOptions::from_config(&config);
+            let aggregation_options = AggregationOptions::from_config(&config);
+            //Reference for norm clipping in the next round
+            let mut previous_global: Option<lib::model::Model> = None;
+            //Hash of the global model the trainer continues from, announced in every update
+            let mut base_model_hash: Option<String> = None;
+            println!("Aggregating rounds with {}", aggregation_options.aggregator);
+            //Every participant seen so far, used to tell which peers did not deliver a round
+            let mut known_participants: BTreeSet<String> = BTreeSet::new();
 
             
-            let mut port = env::var("PORT").unwrap();
-
-            let mut addr = String::from("tcp://*:");
-            addr.push_str(&port);
+            let addr = format!("tcp://*:{}", config.port);
 
             let ctx = zmq::Context::new();
             let socket = ctx.socket(zmq::REP).unwrap();
             assert!(socket.bind(&addr).is_ok());
 
-            let mut clients_number_str = env::var("CLIENTS").unwrap();
-            clients_number_str = clients_number_str.trim_end().to_owned();
-            let mut clients_number = clients_number_str.parse::<usize>().unwrap();
-
             
             _ = socket.recv_string(0).unwrap();
             _ = socket.send("go",0).unwrap();
             let now = Instant::now();
 
-            while round < 10 {
+            loop {
                 println!("Round {} begins", round.to_string());
-                _ = socket.recv_string(0).unwrap();
-
-                match lib::create_ipfs_content(user.as_ref().unwrap()).await {
-                    Ok(_) => {
+                let report = TrainerReport::parse(&socket.recv_string(0).unwrap().unwrap_or_default());
+                if let (Some(loss), Some(accuracy)) = (report.loss, report.accuracy) {
+                    println!("Trainer reported loss {} and accuracy {}", loss, accuracy);
+                }
 
-                        let file = fs::read_to_string("/mnt/ipfs_content.txt").unwrap();
-                        let data = Cursor::new(file);
+                //Other clients reject updates signed with an expired VC
+                if !renew_vc_if_needed(&config, &did, user.as_ref().unwrap()).await {
+                    stop_trainer(&socket);
+                    break
+                }
 
+                match lib::upload_model(&config, &client).await {
+                    Ok(published) => {
+                        println!("Model uploaded to IPFS! CID: {}", published.cid);
 
-                        let cid = match client.add(data).await {
-                            Ok(res) => res.hash,
-                            Err(e) => {
-                                eprintln!("Error: {:?}", e);
-                                return
+                        let vc: String = match lib::read_vc(&config) {
+                            Ok(vc) => vc,
+                            Err(err) => {
+                                eprintln!("Error: {:?}", err);
+                                stop_trainer(&socket);
+                                break
                             },
                         };
-
-                        println!("Model uploaded to IPFS! CID: {}", cid);
-
-                        let vc: String = match lib::read_vc() {
-                            Ok(vc) => vc,
+                        let update = match ModelUpdate::new(session.clone(), round, &published, base_model_hash.clone(), &vc) {
+                            Ok(update) => update,
                             Err(err) => {
                                 eprintln!("Error: {:?}", err);
-                                return
+                                stop_trainer(&socket);
+                                break
                             },
                         };
-                        match lib::upload_to_tangle(user.as_mut().unwrap(), cid, vc, &round.to_string()).await {
-                            Ok(_) => {
-                                println!("Content uploaded to tangle!");
-                                let models = match lib::get_models(&client, &round.to_string(), issuer_did.as_ref().unwrap(), &clients_number).await {
+                        match lib::upload_to_tangle(&config, user.as_mut().unwrap(), update).await {
+                            Ok(message_id) => {
+                                println!("Content uploaded to tangle! Message ID: {}", message_id);
+                                //Only when every client aggregates the same models do they share the global model
+                                let context = RoundContext {
+                                    session: session.clone(),
+                                    round,
+                                    architecture: Some(published.architecture.clone()),
+                                    base_model_hash: if round_options.quorum == config.clients { base_model_hash.clone() } else { None },
+                                };
+                                let round_models = match lib::get_models(&config, &client, &context, issuer_did.as_ref().unwrap(), &round_options).await {
                                     Ok(models) => models,
                                     Err(err) => {
                                         eprintln!("Error: {:?}", err);
-                                        return
+                                        stop_trainer(&socket);
+                                        break
+                                    },
+                                };
+
+                                for (message_id, reason) in &round_models.rejected {
+                                    println!("Round {}: rejected message {}: {}", round, message_id, reason);
+                                }
+
+                                let participants: BTreeSet<String> = round_models.participants.iter().map(|did| did.to_string()).collect();
+                                let missing: Vec<&String> = known_participants.difference(&participants).collect();
+                                let unknown_missing = config.clients.saturating_sub(participants.len() + missing.len());
+                                if !missing.is_empty() || unknown_missing > 0 {
+                                    println!("Round {}: no model from {:?} and {} unknown peers", round, missing, unknown_missing);
+                                }
+                                known_participants.extend(participants);
+
+                                if !round_models.quorum_reached {
+                                    eprintln!("Error: round {} timed out with {} of {} required models", round, round_models.models.len(), round_options.quorum);
+                                    stop_trainer(&socket);
+                                    break
+                                }
+
+                                println!("Retrieved and verified {} models.", round_models.models.len());
+
+                                let global_model = match aggregation::aggregate(&round_models.models, previous_global.as_ref(), &aggregation_options) {
+                                    Ok(model) => model,
+                                    Err(err) => {
+                                        eprintln!("Error: {:?}", err);
+                                        stop_trainer(&socket);
+                                        break
                                     },
                                 };
-                                let serialized = serde_json::to_string(&models).unwrap();
-                                let mut output = File::create("/mnt/models.json").unwrap();
-                                write!(output, "{}", &serialized).unwrap();
-                                println!("Retrieved and verified all models.");
+                                if let Err(err) = lib::write_global_model(&config, &global_model) {
+                                    eprintln!("Error: {:?}", err);
+                                    stop_trainer(&socket);
+                                    break
+                                }
+                                match global_model.num_samples {
+                                    Some(samples) => println!("Global model aggregated over {} samples.", samples),
+                                    None => println!("Global model aggregated with equal weights."),
+                                }
+                                base_model_hash = match global_model.hash() {
+                                    Ok(hash) => Some(hash),
+                                    Err(err) => {
+                                        eprintln!("Error: {:?}", err);
+                                        stop_trainer(&socket);
+                                        break
+                                    },
+                                };
+                                previous_global = Some(global_model);
                             },
                             Err(err) => {
                                 eprintln!("Error: {:?}", err);
-                                return
+                                stop_trainer(&socket);
+                                break
                             },
                         }
                     },
                     Err(err) => {
                         eprintln!("Error: {:?}", err);
-                        return
+                        stop_trainer(&socket);
+                        break
                     },
                 };
                 round += 1;
-                if round == 10 {
+                if let Some(reason) = policy.should_stop(round, now.elapsed(), &report) {
+                    println!("Stopping after {} rounds: {}", round, reason);
                     _ = socket.send("stop",0).unwrap();
                     latency.push(now.elapsed().as_nanos());
-
+                    break
                 } else {
                     _ = socket.send("0",0).unwrap();
                 }
-                
-                
             };
             
-            let mut f = OpenOptions::new().append(true).create(true).open(format!("/mnt/latency_{}.txt", clients_number_str)).expect("Unable to open file"); 
+            let mut f = OpenOptions::new().append(true).create(true).open(config.path(&format!("latency_{}.txt", config.clients))).expect("Unable to open file"); 
             for l in latency {
                 write!(f, "{}", format!("{}\n", l));
             }
                              
-            stream.write(b"shutdown").unwrap();
             println!("\nClient terminated.");
             return
         },
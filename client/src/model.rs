@@ -0,0 +1,178 @@
+//! Binary container for model weights, stored in the safetensors format.
+//!
+//! Each layer of the Keras model is one little-endian f32 tensor. Tensors are named by their
+//! position (`weight_0000`, `weight_0001`, ...) so the layer order survives the round trip.
+//! The number of samples the model was trained on is kept in the header metadata.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use safetensors::tensor::Metadata;
+use safetensors::{Dtype, SafeTensors, View};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub data: Vec<f32>,
+}
+
+impl View for &Tensor {
+    fn dtype(&self) -> Dtype {
+        Dtype::F32
+    }
+
+    fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    fn data(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.data.iter().flat_map(|value| value.to_le_bytes()).collect())
+    }
+
+    fn data_len(&self) -> usize {
+        self.data.len() * std::mem::size_of::<f32>()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Model {
+    /// Layers in the order the trainer expects them.
+    pub tensors: Vec<Tensor>,
+    /// Number of training samples declared by the trainer, used to weight the aggregation.
+    pub num_samples: Option<u64>,
+}
+
+/// Metadata key holding the declared sample count.
+const NUM_SAMPLES_KEY: &str = "num_samples";
+/// Largest safetensors header accepted, the limit of the safetensors crate.
+const MAX_HEADER_SIZE: u64 = 100_000_000;
+
+impl Model {
+    pub fn tensor_name(position: usize) -> String {
+        format!("weight_{:04}", position)
+    }
+
+    /// Fingerprint of the tensor names and shapes. Models with the same architecture ID can be
+    /// aggregated together.
+    pub fn architecture_id(&self) -> String {
+        layout_id(self.tensors.iter().map(|tensor| (tensor.name.as_str(), tensor.shape.as_slice())))
+    }
+
+    /// Hash of the serialized model, used to refer to a global model.
+    pub fn hash(&self) -> Result<String> {
+        Ok(sha256_hex(&self.to_bytes()?))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Model> {
+        let safetensors = SafeTensors::deserialize(bytes)?;
+        let (_, metadata) = SafeTensors::read_metadata(bytes)?;
+        let num_samples = num_samples(&metadata)?;
+
+        let mut tensors = Vec::with_capacity(safetensors.len());
+        for (name, view) in safetensors.tensors() {
+            if view.dtype() != Dtype::F32 {
+                return Err(Error::Malformed(format!("tensor {} is {:?}, expected F32", name, view.dtype())));
+            }
+            let data: Vec<f32> = view
+                .data()
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            tensors.push(Tensor { name, shape: view.shape().to_vec(), data });
+        }
+        tensors.sort_by(|left, right| left.name.cmp(&right.name));
+
+        Ok(Model { tensors, num_samples })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let metadata = self.num_samples.map(|num_samples| HashMap::from([(NUM_SAMPLES_KEY.to_string(), num_samples.to_string())]));
+        let bytes = safetensors::serialize(self.tensors.iter().map(|tensor| (tensor.name.as_str(), tensor)), &metadata)?;
+        Ok(bytes)
+    }
+
+    pub fn read(path: &Path) -> Result<Model> {
+        Model::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+}
+
+/// What the safetensors header of a model tells, read without decoding its tensors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelHeader {
+    /// [`Model::architecture_id`] of the model.
+    pub architecture: String,
+    pub num_samples: Option<u64>,
+    /// Size of the whole file, header included.
+    pub file_size: u64,
+}
+
+impl ModelHeader {
+    /// Reads the header at the start of `reader`. The bytes read are returned with it, so the
+    /// caller can still pass the whole file on.
+    pub fn read<R: Read>(reader: &mut R) -> Result<(ModelHeader, Vec<u8>)> {
+        let mut len = [0u8; 8];
+        reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        if len > MAX_HEADER_SIZE {
+            return Err(Error::Malformed(format!("header of {} bytes", len)));
+        }
+        let mut bytes = len.to_le_bytes().to_vec();
+        reader.by_ref().take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != 8 + len {
+            return Err(Error::Malformed(String::from("truncated header")));
+        }
+
+        let metadata: Metadata = serde_json::from_slice(&bytes[8..])
+            .map_err(|err| Error::Malformed(format!("invalid header: {}", err)))?;
+        let mut layers = Vec::new();
+        let mut data_len = 0;
+        for (name, info) in metadata.tensors() {
+            if info.dtype != Dtype::F32 {
+                return Err(Error::Malformed(format!("tensor {} is {:?}, expected F32", name, info.dtype)));
+            }
+            data_len = data_len.max(info.data_offsets.1 as u64);
+            layers.push((name, info.shape.clone()));
+        }
+        //Same order as the tensors of a decoded model
+        layers.sort_by(|left, right| left.0.cmp(&right.0));
+
+        let header = ModelHeader {
+            architecture: layout_id(layers.iter().map(|(name, shape)| (name.as_str(), shape.as_slice()))),
+            num_samples: num_samples(&metadata)?,
+            file_size: 8 + len + data_len,
+        };
+        Ok((header, bytes))
+    }
+}
+
+fn num_samples(metadata: &Metadata) -> Result<Option<u64>> {
+    match metadata.metadata().as_ref().and_then(|metadata| metadata.get(NUM_SAMPLES_KEY)) {
+        Some(value) => Ok(Some(value.parse::<u64>()
+            .map_err(|_| Error::Malformed(format!("invalid sample count {:?}", value)))?)),
+        None => Ok(None),
+    }
+}
+
+/// Hash of the name and shape of every layer, in the given order.
+fn layout_id<'a>(layers: impl Iterator<Item = (&'a str, &'a [usize])>) -> String {
+    let layout: String = layers
+        .map(|(name, shape)| format!("{}:{:?};", name, shape))
+        .collect();
+    sha256_hex(layout.as_bytes())
+}
+
+/// Lowercase hex SHA-256 of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(bytes))
+}
@@ -0,0 +1,310 @@
+//! Length-prefixed framing used between the Authorization Service and its clients.
+//!
+//! Every frame on the wire is a 4-byte big-endian length, followed by a 1-byte frame type
+//! and the UTF-8 payload. The length covers the type byte and the payload.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Upper bound on a single frame. The largest frames, VCs and VPs, take a few KiB.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+const TAG_COMMAND: u8 = 0x01;
+const TAG_DID: u8 = 0x02;
+const TAG_VC: u8 = 0x03;
+const TAG_VP: u8 = 0x04;
+const TAG_CHALLENGE: u8 = 0x05;
+const TAG_EXPIRY: u8 = 0x06;
+const TAG_RESULT: u8 = 0x07;
+const TAG_ERROR: u8 = 0x08;
+const TAG_INVITE: u8 = 0x09;
+const TAG_PROOF: u8 = 0x0a;
+
+/// Reason attached to an error frame, so the client can decide how to react.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The DID sent by the client could not be parsed.
+    InvalidDid,
+    /// The presentation failed verification.
+    VpRejected,
+    /// The challenge expired before the presentation was received.
+    ChallengeExpired,
+    /// The issuer could not sign or reach the Tangle.
+    IssuerUnavailable,
+    /// The frame received does not fit the current exchange.
+    BadRequest,
+    /// The client is not allowed to do this, e.g. its credentials were revoked.
+    Forbidden,
+    /// The proof of control of the DID failed verification.
+    ProofRejected,
+    /// The AS already serves as many sessions as it allows.
+    Busy,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidDid => "invalid_did",
+            ErrorCode::VpRejected => "vp_rejected",
+            ErrorCode::ChallengeExpired => "challenge_expired",
+            ErrorCode::IssuerUnavailable => "issuer_unavailable",
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::ProofRejected => "proof_rejected",
+            ErrorCode::Busy => "busy",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<ErrorCode> {
+        match code {
+            "invalid_did" => Some(ErrorCode::InvalidDid),
+            "vp_rejected" => Some(ErrorCode::VpRejected),
+            "challenge_expired" => Some(ErrorCode::ChallengeExpired),
+            "issuer_unavailable" => Some(ErrorCode::IssuerUnavailable),
+            "bad_request" => Some(ErrorCode::BadRequest),
+            "forbidden" => Some(ErrorCode::Forbidden),
+            "proof_rejected" => Some(ErrorCode::ProofRejected),
+            "busy" => Some(ErrorCode::Busy),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// Instruction for the AS: "vc", "renew", "vp", "revoke" or "shutdown".
+    Command(String),
+    /// DID the command is about: the client asking for or renewing a credential, presenting
+    /// one, or the holder to revoke.
+    Did(String),
+    /// Verifiable credential issued by the AS, after the proof of a "vc" or the VP of a "renew".
+    Vc(String),
+    /// Verifiable presentation created by the client, answering the challenge of a "vp" or of a
+    /// "renew", where it presents the VC to replace.
+    Vp(String),
+    /// Challenge the client has to sign in its presentation.
+    Challenge(String),
+    /// RFC 3339 expiry of the challenge.
+    Expiry(String),
+    /// Outcome of a successful exchange (the issuer DID after a verified VP).
+    Result(String),
+    /// Failure reported by the peer, encoded on the wire as "code\nmessage".
+    Error { code: ErrorCode, message: String },
+    /// Invite token following the DID of a "vc" command, empty when the client has none.
+    Invite(String),
+    /// Challenge signed by the client to prove it controls its DID, before a VC is issued.
+    Proof(String),
+}
+
+impl Frame {
+    fn tag(&self) -> u8 {
+        match self {
+            Frame::Command(_) => TAG_COMMAND,
+            Frame::Did(_) => TAG_DID,
+            Frame::Vc(_) => TAG_VC,
+            Frame::Vp(_) => TAG_VP,
+            Frame::Challenge(_) => TAG_CHALLENGE,
+            Frame::Expiry(_) => TAG_EXPIRY,
+            Frame::Result(_) => TAG_RESULT,
+            Frame::Error { .. } => TAG_ERROR,
+            Frame::Invite(_) => TAG_INVITE,
+            Frame::Proof(_) => TAG_PROOF,
+        }
+    }
+
+    fn payload(&self) -> Cow<'_, str> {
+        match self {
+            Frame::Command(p)
+            | Frame::Did(p)
+            | Frame::Vc(p)
+            | Frame::Vp(p)
+            | Frame::Challenge(p)
+            | Frame::Expiry(p)
+            | Frame::Result(p)
+            | Frame::Invite(p)
+            | Frame::Proof(p) => Cow::Borrowed(p),
+            Frame::Error { code, message } => Cow::Owned(format!("{}\n{}", code, message)),
+        }
+    }
+
+    fn from_parts(tag: u8, payload: String) -> io::Result<Frame> {
+        match tag {
+            TAG_COMMAND => Ok(Frame::Command(payload)),
+            TAG_DID => Ok(Frame::Did(payload)),
+            TAG_VC => Ok(Frame::Vc(payload)),
+            TAG_VP => Ok(Frame::Vp(payload)),
+            TAG_CHALLENGE => Ok(Frame::Challenge(payload)),
+            TAG_EXPIRY => Ok(Frame::Expiry(payload)),
+            TAG_RESULT => Ok(Frame::Result(payload)),
+            TAG_INVITE => Ok(Frame::Invite(payload)),
+            TAG_PROOF => Ok(Frame::Proof(payload)),
+            TAG_ERROR => {
+                let (code, message) = payload.split_once('\n').unwrap_or((payload.as_str(), ""));
+                let code = ErrorCode::parse(code)
+                    .ok_or_else(|| invalid_data(format!("unknown error code {}", code)))?;
+                Ok(Frame::Error { code, message: message.to_string() })
+            },
+            other => Err(invalid_data(format!("unknown frame type {:#04x}", other))),
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub fn write_frame<W: Write>(stream: &mut W, frame: &Frame) -> io::Result<()> {
+    let payload = frame.payload();
+    let payload = payload.as_bytes();
+    let len = payload.len() + 1;
+    if len > MAX_FRAME_SIZE {
+        return Err(invalid_data(format!("frame of {} bytes exceeds the limit", len)));
+    }
+
+    stream.write_all(&(len as u32).to_be_bytes())?;
+    stream.write_all(&[frame.tag()])?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+/// Shorthand for building an error frame.
+pub fn error_frame(code: ErrorCode, message: impl Into<String>) -> Frame {
+    Frame::Error { code, message: message.into() }
+}
+
+pub fn read_frame<R: Read>(stream: &mut R) -> io::Result<Frame> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_be_bytes(header) as usize;
+    if len == 0 || len > MAX_FRAME_SIZE {
+        return Err(invalid_data(format!("invalid frame length {}", len)));
+    }
+
+    let mut tag = [0u8; 1];
+    stream.read_exact(&mut tag)?;
+
+    //Grows with the bytes actually received, a peer announcing a large frame gets no buffer up front
+    let mut payload = Vec::new();
+    (&mut *stream).take(len as u64 - 1).read_to_end(&mut payload)?;
+    if payload.len() != len - 1 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame"));
+    }
+    let payload = String::from_utf8(payload).map_err(|err| invalid_data(err.to_string()))?;
+
+    Frame::from_parts(tag[0], payload)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_frame() -> Vec<Frame> {
+        vec![
+            Frame::Command(String::from("renew")),
+            Frame::Did(String::from("did:iota:dev:abc")),
+            Frame::Vc(String::from("{\"type\":\"VerifiableCredential\"}")),
+            Frame::Vp(String::from("{\"type\":\"VerifiablePresentation\"}")),
+            Frame::Challenge(String::from("1234567890")),
+            Frame::Expiry(String::from("2026-10-18T10:00:00Z")),
+            Frame::Result(String::from("did:iota:dev:issuer")),
+            error_frame(ErrorCode::VpRejected, "invalid credential:\nexpired"),
+            Frame::Invite(String::new()),
+            Frame::Proof(String::from("{\"type\":\"FetaDidControlProof\"}")),
+        ]
+    }
+
+    const EVERY_CODE: [ErrorCode; 8] = [
+        ErrorCode::InvalidDid,
+        ErrorCode::VpRejected,
+        ErrorCode::ChallengeExpired,
+        ErrorCode::IssuerUnavailable,
+        ErrorCode::BadRequest,
+        ErrorCode::Forbidden,
+        ErrorCode::ProofRejected,
+        ErrorCode::Busy,
+    ];
+
+    fn encode(frame: &Frame) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, frame).unwrap();
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Frame> {
+        read_frame(&mut &bytes[..])
+    }
+
+    fn decode_error(bytes: &[u8]) -> io::ErrorKind {
+        decode(bytes).unwrap_err().kind()
+    }
+
+    #[test]
+    fn every_frame_type_round_trips() {
+        for frame in every_frame() {
+            assert_eq!(decode(&encode(&frame)).unwrap(), frame);
+        }
+    }
+
+    //Fixed bytes, the AS and the client must agree on them
+    #[test]
+    fn frames_have_the_documented_layout() {
+        assert_eq!(encode(&Frame::Did(String::from("ab"))), [0, 0, 0, 3, 0x02, b'a', b'b']);
+        assert_eq!(encode(&Frame::Proof(String::new())), [0, 0, 0, 1, 0x0a]);
+        assert_eq!(encode(&error_frame(ErrorCode::Busy, "x")), [&[0, 0, 0, 7, 0x08][..], b"busy\nx"].concat());
+    }
+
+    #[test]
+    fn every_error_code_parses_back() {
+        for code in EVERY_CODE {
+            assert_eq!(ErrorCode::parse(code.as_str()), Some(code));
+        }
+        assert_eq!(ErrorCode::parse("unknown"), None);
+    }
+
+    #[test]
+    fn error_frames_split_code_and_message() {
+        let frame = [&[0, 0, 0, 10, 0x08][..], b"forbidden"].concat();
+        assert_eq!(decode(&frame).unwrap(), error_frame(ErrorCode::Forbidden, ""));
+        let frame = [&[0, 0, 0, 8, 0x08][..], b"nope\nno"].concat();
+        assert_eq!(decode_error(&frame), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let payload = "a".repeat(MAX_FRAME_SIZE);
+        let mut bytes = Vec::new();
+        assert!(write_frame(&mut bytes, &Frame::Vc(payload)).is_err());
+        assert!(bytes.is_empty());
+
+        let len = (MAX_FRAME_SIZE as u32 + 1).to_be_bytes();
+        assert_eq!(decode_error(&[&len[..], &[0x03]].concat()), io::ErrorKind::InvalidData);
+        assert_eq!(decode_error(&[0, 0, 0, 0]), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_frames_are_refused() {
+        assert_eq!(decode_error(&[0, 0]), io::ErrorKind::UnexpectedEof);
+        assert_eq!(decode_error(&[0, 0, 0, 3]), io::ErrorKind::UnexpectedEof);
+        let frame = encode(&Frame::Did(String::from("did:iota:dev:abc")));
+        assert_eq!(decode_error(&frame[..frame.len() - 1]), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn unknown_tags_are_refused() {
+        assert_eq!(decode_error(&[0, 0, 0, 2, 0xff, b'a']), io::ErrorKind::InvalidData);
+        assert_eq!(decode_error(&[0, 0, 0, 1, 0x00]), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn payloads_must_be_utf8() {
+        assert_eq!(decode_error(&[0, 0, 0, 3, 0x02, 0xc3, 0x28]), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,105 @@
+//! Decides when the federation stops, based on the configuration and on what the Python
+//! trainer reports over the ZMQ control channel.
+
+use std::fmt;
+use std::time::Duration;
+use serde::Deserialize;
+
+use crate::config::Config;
+
+/// Message sent by the trainer at the beginning of every round.
+///
+/// Older trainers only send "1", which is read as a report without metrics.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct TrainerReport {
+    #[serde(default)]
+    pub loss: Option<f64>,
+    #[serde(default)]
+    pub accuracy: Option<f64>,
+    /// Set by the trainer once the global model stopped improving.
+    #[serde(default)]
+    pub converged: bool,
+}
+
+impl TrainerReport {
+    pub fn parse(msg: &str) -> TrainerReport {
+        serde_json::from_str(msg).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    MaxRounds,
+    TimeBudget,
+    Converged,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::MaxRounds => write!(f, "maximum number of rounds reached"),
+            StopReason::TimeBudget => write!(f, "time budget exhausted"),
+            StopReason::Converged => write!(f, "the trainer reported convergence"),
+        }
+    }
+}
+
+/// First delay between two polls of the round index.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Longest delay between two polls when no new update shows up.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a client waits for the models of a round, and how many it needs.
+#[derive(Debug, Clone)]
+pub struct RoundOptions {
+    pub deadline: Duration,
+    /// Number of models after which the round is aggregated.
+    pub quorum: usize,
+    pub poll_interval: Duration,
+    pub max_poll_interval: Duration,
+    /// Largest content, in bytes, downloaded for one update.
+    pub max_model_size: u64,
+}
+
+impl RoundOptions {
+    pub fn from_config(config: &Config) -> RoundOptions {
+        RoundOptions {
+            deadline: Duration::from_secs(config.round_timeout_secs),
+            quorum: config.quorum.unwrap_or(config.clients),
+            poll_interval: POLL_INTERVAL,
+            max_poll_interval: MAX_POLL_INTERVAL,
+            max_model_size: config.max_model_size_mb.saturating_mul(1024 * 1024),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoundPolicy {
+    pub max_rounds: u32,
+    pub time_budget: Option<Duration>,
+}
+
+impl RoundPolicy {
+    pub fn from_config(config: &Config) -> RoundPolicy {
+        RoundPolicy {
+            max_rounds: config.rounds,
+            time_budget: config.time_budget_secs.map(Duration::from_secs),
+        }
+    }
+
+    /// Checked after every completed round, `None` means another round follows.
+    pub fn should_stop(&self, completed_rounds: u32, elapsed: Duration, report: &TrainerReport) -> Option<StopReason> {
+        if completed_rounds >= self.max_rounds {
+            return Some(StopReason::MaxRounds);
+        }
+        if let Some(budget) = self.time_budget {
+            if elapsed >= budget {
+                return Some(StopReason::TimeBudget);
+            }
+        }
+        if report.converged {
+            return Some(StopReason::Converged);
+        }
+        None
+    }
+}
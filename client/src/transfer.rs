@@ -0,0 +1,123 @@
+//! Hashing of IPFS content while it is streamed, so models are never copied in full just to
+//! be hashed.
+//!
+//! The IPFS object of an update is the safetensors model as written by the trainer. Its hash
+//! and size are signed in the Tangle envelope.
+
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+/// Reader hashing everything read through it. The hash can be taken through a
+/// [`HashTracker`] after the reader itself has been handed over, e.g. to the IPFS client.
+pub struct HashingReader<R> {
+    inner: R,
+    state: Arc<Mutex<HashState>>,
+}
+
+struct HashState {
+    hasher: Sha256,
+    len: u64,
+}
+
+#[derive(Clone)]
+pub struct HashTracker {
+    state: Arc<Mutex<HashState>>,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> (HashingReader<R>, HashTracker) {
+        let state = Arc::new(Mutex::new(HashState { hasher: Sha256::new(), len: 0 }));
+        (HashingReader { inner, state: state.clone() }, HashTracker { state })
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut state = self.state.lock().unwrap();
+        state.hasher.update(&buf[..read]);
+        state.len += read as u64;
+        Ok(read)
+    }
+}
+
+impl HashTracker {
+    /// Lowercase hex SHA-256 and length of what was read so far.
+    pub fn finish(&self) -> (String, u64) {
+        let state = self.state.lock().unwrap();
+        (base16ct::lower::encode_string(&state.hasher.clone().finalize()), state.len)
+    }
+}
+
+/// Collects a downloaded model chunk by chunk, hashing it on the way and refusing content
+/// larger than the limit.
+pub struct Download {
+    max_size: u64,
+    hasher: Sha256,
+    bytes: Vec<u8>,
+}
+
+impl Download {
+    pub fn new(max_size: u64) -> Download {
+        Download { max_size, hasher: Sha256::new(), bytes: Vec::new() }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<()> {
+        if (self.bytes.len() + chunk.len()) as u64 > self.max_size {
+            return Err(Error::Malformed(format!("content larger than {} bytes", self.max_size)));
+        }
+        self.hasher.update(chunk);
+        self.bytes.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    /// Downloaded bytes and their lowercase hex SHA-256.
+    pub fn finish(self) -> (Vec<u8>, String) {
+        (self.bytes, base16ct::lower::encode_string(&self.hasher.finalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::sha256_hex;
+
+    #[test]
+    fn download_accepts_content_up_to_the_limit() {
+        let mut download = Download::new(10);
+        download.push(b"0123").unwrap();
+        download.push(b"456789").unwrap();
+        let (bytes, hash) = download.finish();
+        assert_eq!(bytes, b"0123456789");
+        assert_eq!(hash, sha256_hex(b"0123456789"));
+    }
+
+    #[test]
+    fn download_refuses_content_past_the_limit() {
+        let mut download = Download::new(10);
+        download.push(b"0123456789").unwrap();
+        assert!(download.push(b"a").is_err());
+        //The refused chunk is neither kept nor hashed
+        let (bytes, hash) = download.finish();
+        assert_eq!(bytes, b"0123456789");
+        assert_eq!(hash, sha256_hex(b"0123456789"));
+    }
+
+    #[test]
+    fn download_refuses_a_single_oversized_chunk() {
+        let mut download = Download::new(3);
+        assert!(download.push(b"abcd").is_err());
+        assert!(download.finish().0.is_empty());
+    }
+
+    #[test]
+    fn hashing_reader_hashes_what_is_read() {
+        let (mut reader, tracker) = HashingReader::new(&b"model"[..]);
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+        assert_eq!(tracker.finish(), (sha256_hex(b"model"), 5));
+    }
+}
@@ -7,13 +7,30 @@ import json
 import numpy as np
 import zmq
 import time
+from safetensors.numpy import save_file, load_file
 tf.random.set_seed(42)
 
 
+def save_weights(weights, path, num_samples):
+    # One float32 tensor per layer, named by position so the order is kept. The sample count
+    # weights this model in the aggregation done by the Rust client.
+    tensors = {"weight_%04d" % i: np.ascontiguousarray(w, dtype=np.float32) for i, w in enumerate(weights)}
+    save_file(tensors, path, metadata={"num_samples": str(num_samples)})
+
+
+def load_weights(path):
+    tensors = load_file(path)
+    return [tensors[name] for name in sorted(tensors)]
+
+
 context = zmq.Context()
 socket = context.socket(zmq.REQ)
 socket.connect("tcp://localhost:" + os.getenv("PORT"))
 clients = os.getenv("CLIENTS")
+# Convergence is reported to the Rust client when the global accuracy improved by less than
+# CONVERGENCE_DELTA for CONVERGENCE_PATIENCE consecutive rounds. Unset means never.
+convergence_delta = os.getenv("CONVERGENCE_DELTA")
+convergence_patience = int(os.getenv("CONVERGENCE_PATIENCE", "3"))
 
 nn_model = tf.keras.models.Sequential([
   tf.keras.layers.Flatten(input_shape=(28, 28)),
@@ -42,27 +59,26 @@ socket.send_string("1")
 socket.recv_string()
 accuracies_local = []
 accuracies_global = []
+
+
+def converged():
+    if convergence_delta is None or len(accuracies_global) <= convergence_patience:
+        return False
+    recent = accuracies_global[-(convergence_patience + 1):]
+    return all(b - a < float(convergence_delta) for a, b in zip(recent, recent[1:]))
+
+
 start = time.time()
 while True:
     nn_model.fit(X, Y, epochs=5, batch_size=32, steps_per_epoch=3)
     loss, accuracy = nn_model.evaluate(X_test, Y_test)
     accuracies_local.append(accuracy)
-    arr = nn_model.get_weights()
-    f = [w.tolist() for w in arr]
-    with open("/mnt/simple.json", "w") as outfile:
-        outfile.write(json.dumps(f))
+    save_weights(nn_model.get_weights(), "/mnt/model.safetensors", len(X))
 
-    socket.send_string("1")
+    socket.send_string(json.dumps({"loss": loss, "accuracy": accuracy, "converged": converged()}))
     res = socket.recv_string()
     
-    with open("/mnt/models.json", "r") as infile:
-        models = json.load(infile)
-    models = [np.array(json.loads(model), dtype=object) for model in models]
-    newGM = []
-    for model in zip(*models):
-        newGM.append(np.array(model).mean(axis=0))
-
-    nn_model.set_weights(newGM)
+    nn_model.set_weights(load_weights("/mnt/global_model.safetensors"))
     loss, accuracy = nn_model.evaluate(X_test, Y_test)
     accuracies_global.append(accuracy)
     if res == "stop":